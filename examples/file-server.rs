@@ -2,6 +2,7 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 
 use async_trait::async_trait;
+use cliud::compress::PrecompressedMiddleware;
 use cliud::http::{Request, Response};
 use cliud::middleware::{Middleware, Next};
 use cliud::server::Server;
@@ -16,6 +17,7 @@ async fn main() -> std::io::Result<()> {
     println!("Listening on {address}");
 
     let server = Server::<std::io::Error, _>::default()
+        .with_middleware(PrecompressedMiddleware::new("./", "/file/"))
         .with_middleware(FileServerMiddleware {
             root: "./",
             endpoint: "/file/",
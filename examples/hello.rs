@@ -6,7 +6,7 @@ use std::net::SocketAddr;
 use async_trait::async_trait;
 use cliud::BoxError;
 use cliud::http::{Request, Response};
-use cliud::middleware::{Middleware, Next};
+use cliud::middleware::{Middleware, Next, RequestIdMiddleware};
 use cliud::server::Server;
 use tokio::net::TcpListener;
 
@@ -24,6 +24,7 @@ async fn main() -> std::io::Result<()> {
     println!("Listening on {address}");
 
     let server = Server::<BoxError, _>::default()
+        .with_middleware(RequestIdMiddleware::new())
         .with_middleware(RouterMiddleware)
         .leak();
 
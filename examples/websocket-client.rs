@@ -0,0 +1,51 @@
+use std::ops::{Deref, DerefMut};
+
+use cliud::websocket::client::ClientHandshake;
+use cliud::websocket::{CloseFrame, Message, Result, WebSocket, WebSocketExt as _, WebSocketState};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, RwLock};
+
+struct EchoClient {
+    stream: Mutex<TcpStream>,
+    state: RwLock<WebSocketState>,
+}
+
+impl WebSocket for EchoClient {
+    type Stream = TcpStream;
+
+    async fn state(&self) -> impl Deref<Target = WebSocketState> {
+        self.state.read().await
+    }
+
+    async fn state_mut(&self) -> impl DerefMut<Target = WebSocketState> {
+        self.state.write().await
+    }
+
+    async fn stream_mut(&self) -> impl DerefMut<Target = Self::Stream> {
+        self.stream.lock().await
+    }
+
+    async fn on_message(&mut self, message: Message) -> Result<()> {
+        println!("echoed: {message:?}");
+        self.send_close(CloseFrame::normal()).await
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // run the `websocket` example first
+    let mut stream = TcpStream::connect("127.0.0.1:4223").await?;
+    let state = ClientHandshake::new("127.0.0.1:4223", "/ws")
+        .with_protocols(["echo"])
+        .with_deflate(true)
+        .perform(&mut stream)
+        .await?;
+    println!("connected with subprotocol {:?}", state.protocol());
+
+    let mut client = EchoClient {
+        stream: Mutex::new(stream),
+        state: RwLock::new(state),
+    };
+    client.send_text("Hello, WebSocket!".to_owned()).await?;
+    client.run().await
+}
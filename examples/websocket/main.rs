@@ -6,9 +6,11 @@ use cliud::BoxError;
 use cliud::http::{Request, Response};
 use cliud::middleware::{Middleware, Next};
 use cliud::server::Server;
-use cliud::service::{ConnectionFlag, Service};
-use cliud::websocket::{Result, WebSocket, WebSocketExt as _, WebSocketState};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use cliud::upgrade::Upgraded;
+use cliud::websocket::{
+    CloseFrame, DeflateConfig, Message, Result, WebSocket, WebSocketExt as _, WebSocketHandshakeMiddleware,
+    WebSocketState,
+};
 use tokio::net::TcpListener;
 use tokio::sync::{Mutex, RwLock};
 
@@ -20,8 +22,19 @@ async fn main() -> std::io::Result<()> {
 
     let server = Server::<BoxError, _>::default()
         .with_middleware(RouterMiddleware)
-        .with_middleware(cliud::websocket::WebSocketHandshakeMiddleware)
-        .with_service(EchoWebSocketService)
+        .with_middleware(
+            WebSocketHandshakeMiddleware::new()
+                .with_deflate(DeflateConfig::default())
+                .with_protocols(["echo"])
+                .with_allowed_origins(["http://127.0.0.1:4223", "http://localhost:4223"])
+                .with_handler(|stream, state| async move {
+                    let mut socket = EchoWebSocket {
+                        stream: Mutex::new(stream),
+                        state: RwLock::new(state),
+                    };
+                    Ok(socket.run().await?)
+                }),
+        )
         .leak();
 
     loop {
@@ -43,17 +56,13 @@ impl<E: From<std::io::Error>> Middleware<E> for RouterMiddleware {
     }
 }
 
-struct EchoWebSocket<'a, S> {
-    stream: Mutex<&'a mut S>,
-    address: &'a SocketAddr,
+struct EchoWebSocket {
+    stream: Mutex<Upgraded>,
     state: RwLock<WebSocketState>,
 }
 
-impl<'a, S> WebSocket for EchoWebSocket<'a, S>
-where
-    S: AsyncReadExt + AsyncWriteExt + Unpin + Send,
-{
-    type Stream = &'a mut S;
+impl WebSocket for EchoWebSocket {
+    type Stream = Upgraded;
 
     async fn state(&self) -> impl Deref<Target = WebSocketState> {
         self.state.read().await
@@ -67,46 +76,15 @@ where
         self.stream.lock().await
     }
 
-    async fn on_message(&mut self, message: Vec<u8>) -> Result<()> {
-        eprintln!("receive message from {}: {message:?}", self.address);
-        self.send_binary(message).await?;
+    async fn on_message(&mut self, message: Message) -> Result<()> {
+        let protocol = self.state().await.protocol().map(str::to_owned);
+        eprintln!("receive message ({protocol:?}): {message:?}");
+        self.send_message(message).await?;
         Ok(())
     }
 
-    async fn on_close(&mut self, reason: Vec<u8>) -> Result<()> {
-        eprintln!("disconnected with {}: {reason:?}", self.address);
+    async fn on_close(&mut self, frame: CloseFrame) -> Result<()> {
+        eprintln!("disconnected: {} {}", frame.code, frame.reason);
         Ok(())
     }
 }
-
-struct EchoWebSocketService;
-
-#[async_trait]
-impl<E, S> Service<E, S> for EchoWebSocketService
-where
-    E: From<std::io::Error> + From<cliud::websocket::Error>,
-    S: AsyncReadExt + AsyncWriteExt + Unpin + Send,
-{
-    async fn call(
-        &self,
-        _request: &Request,
-        response: &Response,
-        address: &SocketAddr,
-        stream: &mut S,
-    ) -> Result<ConnectionFlag, E> {
-        if let Some(upgarde) = response.headers.get("Upgrade")
-            && upgarde == "websocket"
-        {
-            EchoWebSocket {
-                stream: Mutex::new(stream),
-                state: RwLock::new(WebSocketState::default()),
-                address,
-            }
-            .run()
-            .await?;
-            Ok(ConnectionFlag::Close)
-        } else {
-            Ok(ConnectionFlag::Continue)
-        }
-    }
-}
@@ -0,0 +1,614 @@
+use std::fmt::{Debug, Formatter, Write as _};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal as _, Write};
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use colored::Colorize as _;
+use smol_str::SmolStr;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::middleware::RequestIdMiddleware;
+use crate::service::{Exchange, Observer};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A UTC timestamp broken down into its calendar fields.
+struct Timestamp {
+    year: i64,
+    month: usize,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    millis: u32,
+}
+
+impl Timestamp {
+    fn new(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = i64::try_from(since_epoch.as_secs()).unwrap_or(i64::MAX);
+        let (days, of_day) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+        // converts days since the epoch to a date of the proleptic Gregorian calendar, as in Howard Hinnant's
+        // `civil_from_days`
+        let shifted = days + 719_468;
+        let era = shifted.div_euclid(146_097);
+        let day_of_era = shifted.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era.div_euclid(1460) + day_of_era.div_euclid(36_524)
+            - day_of_era.div_euclid(146_096))
+        .div_euclid(365);
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era.div_euclid(4) - year_of_era.div_euclid(100));
+        let month_from_march = (5 * day_of_year + 2).div_euclid(153);
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        Self {
+            year: year_of_era + era * 400 + i64::from(month <= 2),
+            month: usize::try_from(month).unwrap_or(1),
+            day: day_of_year - (153 * month_from_march + 2).div_euclid(5) + 1,
+            hour: of_day.div_euclid(3600),
+            minute: of_day.rem_euclid(3600).div_euclid(60),
+            second: of_day.rem_euclid(60),
+            millis: since_epoch.subsec_millis(),
+        }
+    }
+
+    /// As in the Common Log Format, such as `[10/Oct/2000:13:55:36 +0000]`.
+    fn clf(&self) -> String {
+        let month = MONTHS.get(self.month.saturating_sub(1)).unwrap_or(&"Jan");
+        format!(
+            "[{:02}/{month}/{}:{:02}:{:02}:{:02} +0000]",
+            self.day, self.year, self.hour, self.minute, self.second
+        )
+    }
+
+    /// As in RFC 3339, such as `2000-10-10T13:55:36.000Z`.
+    fn rfc3339(&self) -> String {
+        format!(
+            "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millis
+        )
+    }
+}
+
+/// Escapes quotes, backslashes and control characters, so a client cannot forge log lines.
+#[expect(clippy::let_underscore_must_use, reason = "writing to a `String` cannot fail")]
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for char in value.chars() {
+        match char {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            control if control.is_control() => {
+                let _ = write!(escaped, "\\x{:02x}", u32::from(control));
+            }
+            other => escaped.push(other),
+        }
+    }
+    escaped
+}
+
+#[expect(clippy::let_underscore_must_use, reason = "writing to a `String` cannot fail")]
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len().saturating_add(2));
+    json.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            control if control.is_control() => {
+                let _ = write!(json, "\\u{:04x}", u32::from(control));
+            }
+            other => json.push(other),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn json_or_null(value: Option<&SmolStr>) -> String {
+    value.map_or_else(|| "null".to_owned(), |value| json_string(value))
+}
+
+fn colorize_status(status: &str) -> String {
+    let color = match status.chars().next() {
+        Some('1') => "cyan",
+        Some('2') => "green",
+        Some('3') => "yellow",
+        Some('4') => "red",
+        Some('5') => "purple",
+        _ => "normal",
+    };
+    status.color(color).to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Directive {
+    Literal(String),
+    RemoteAddress,
+    Unknown,
+    Time,
+    RequestLine,
+    Method,
+    Path,
+    Query,
+    Protocol,
+    Status,
+    BodySize,
+    BodyBytes,
+    BytesWritten,
+    Micros,
+    Seconds,
+    RequestHeader(SmolStr),
+    ResponseHeader(SmolStr),
+}
+
+/// A log line template made of Apache `mod_log_config` directives.
+///
+/// Supported are `%h`, `%a`, `%l`, `%u`, `%t`, `%r`, `%m`, `%U`, `%q`, `%H`, `%s`, `%>s`, `%b`, `%B`, `%O`, `%D`,
+/// `%T`, `%{Name}i`, `%{Name}o` and `%%`; anything else is written as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    directives: Vec<Directive>,
+}
+
+impl Template {
+    pub const COMMON: &str = r#"%h %l %u %t "%r" %>s %b"#;
+    pub const COMBINED: &str = r#"%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-Agent}i""#;
+
+    #[inline]
+    pub fn parse(template: &str) -> Self {
+        let mut directives = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        while let Some((before, after)) = rest.split_once('%') {
+            literal.push_str(before);
+            let (directive, after) = Self::parse_directive(after);
+            match directive {
+                Some(Directive::Literal(text)) => literal.push_str(&text),
+                Some(directive) => {
+                    if !literal.is_empty() {
+                        directives.push(Directive::Literal(std::mem::take(&mut literal)));
+                    }
+                    directives.push(directive);
+                }
+                None => literal.push('%'),
+            }
+            rest = after;
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            directives.push(Directive::Literal(literal));
+        }
+        Self { directives }
+    }
+
+    /// Parses what follows a `%`, returning `None` for an unsupported directive, which is then kept as text.
+    fn parse_directive(after: &str) -> (Option<Directive>, &str) {
+        if let Some(braced) = after.strip_prefix('{')
+            && let Some((name, rest)) = braced.split_once('}')
+        {
+            return match rest.chars().next() {
+                Some('i') => (
+                    Some(Directive::RequestHeader(name.into())),
+                    rest.get(1..).unwrap_or_default(),
+                ),
+                Some('o') => (
+                    Some(Directive::ResponseHeader(name.into())),
+                    rest.get(1..).unwrap_or_default(),
+                ),
+                Some(_) | None => (None, after),
+            };
+        }
+        let after = after.strip_prefix('>').unwrap_or(after);
+        let mut chars = after.chars();
+        let directive = match chars.next() {
+            Some('%') => Directive::Literal("%".to_owned()),
+            Some('h' | 'a') => Directive::RemoteAddress,
+            Some('l' | 'u') => Directive::Unknown,
+            Some('t') => Directive::Time,
+            Some('r') => Directive::RequestLine,
+            Some('m') => Directive::Method,
+            Some('U') => Directive::Path,
+            Some('q') => Directive::Query,
+            Some('H') => Directive::Protocol,
+            Some('s') => Directive::Status,
+            Some('b') => Directive::BodySize,
+            Some('B') => Directive::BodyBytes,
+            Some('O') => Directive::BytesWritten,
+            Some('D') => Directive::Micros,
+            Some('T') => Directive::Seconds,
+            Some(_) | None => return (None, after),
+        };
+        (Some(directive), chars.as_str())
+    }
+
+    #[expect(clippy::pattern_type_mismatch, reason = "binding the header names by reference")]
+    fn render(&self, exchange: &Exchange<'_>, colorize: bool) -> String {
+        let request = exchange.request;
+        let response = exchange.response;
+        let target = request.map(|request| request.target.split_once('?').unwrap_or((&request.target, "")));
+        let mut line = String::new();
+        for directive in &self.directives {
+            let field = match directive {
+                Directive::Literal(text) => text.clone(),
+                Directive::RemoteAddress => exchange.peer.ip().to_string(),
+                Directive::Unknown => "-".to_owned(),
+                Directive::Time => Timestamp::new(exchange.received).clf(),
+                Directive::RequestLine => {
+                    request.map_or_else(|| "-".to_owned(), |request| escape(&request.request_line()))
+                }
+                Directive::Method => request.map_or_else(|| "-".to_owned(), |request| escape(&request.method)),
+                Directive::Path => target.map_or_else(|| "-".to_owned(), |(path, _)| escape(path)),
+                Directive::Query => match target {
+                    Some((_, query)) if !query.is_empty() => format!("?{}", escape(query)),
+                    Some(_) | None => String::new(),
+                },
+                Directive::Protocol => request.map_or_else(|| "-".to_owned(), |request| escape(&request.version)),
+                Directive::Status if colorize => colorize_status(&response.status_code),
+                Directive::Status => escape(&response.status_code),
+                Directive::BodySize if response.body.is_empty() => "-".to_owned(),
+                Directive::BodySize | Directive::BodyBytes => response.body.len().to_string(),
+                Directive::BytesWritten => exchange.bytes_written.to_string(),
+                Directive::Micros => exchange.duration().as_micros().to_string(),
+                Directive::Seconds => exchange.duration().as_secs().to_string(),
+                Directive::RequestHeader(name) => request
+                    .and_then(|request| request.headers.get(name))
+                    .map_or_else(|| "-".to_owned(), |value| escape(value)),
+                Directive::ResponseHeader(name) => response
+                    .headers
+                    .get(name)
+                    .map_or_else(|| "-".to_owned(), |value| escape(value)),
+            };
+            line.push_str(&field);
+        }
+        line
+    }
+}
+
+static COMMON: LazyLock<Template> = LazyLock::new(|| Template::parse(Template::COMMON));
+static COMBINED: LazyLock<Template> = LazyLock::new(|| Template::parse(Template::COMBINED));
+
+/// How each access log line is laid out.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// The peer, request line, status, response size and duration, and the request ID if any.
+    #[default]
+    Short,
+    /// The Apache Common Log Format.
+    Common,
+    /// The Apache Combined Log Format, which adds the referrer and user agent to the Common one.
+    Combined,
+    /// One JSON object per line.
+    Json,
+    Custom(Template),
+}
+
+impl LogFormat {
+    #[inline]
+    pub fn custom(template: &str) -> Self {
+        Self::Custom(Template::parse(template))
+    }
+
+    #[expect(clippy::pattern_type_mismatch, reason = "binding the template by reference")]
+    fn render(&self, exchange: &Exchange<'_>, colorize: bool) -> String {
+        match self {
+            Self::Short => Self::short(exchange, colorize),
+            Self::Common => COMMON.render(exchange, colorize),
+            Self::Combined => COMBINED.render(exchange, colorize),
+            Self::Json => Self::json(exchange),
+            Self::Custom(template) => template.render(exchange, colorize),
+        }
+    }
+
+    fn short(exchange: &Exchange<'_>, colorize: bool) -> String {
+        let request_line = exchange
+            .request
+            .map_or_else(|| "-".to_owned(), |request| escape(&request.request_line()));
+        let status = &exchange.response.status_code;
+        // set by `RequestIdMiddleware`, to find the other lines logged for this request
+        let request_id = exchange
+            .response
+            .headers
+            .get(RequestIdMiddleware::HEADER)
+            .map(|id| format!(" [{}]", escape(id)))
+            .unwrap_or_default();
+        let (request_line, status, request_id) = if colorize {
+            (
+                request_line.bright_cyan().to_string(),
+                colorize_status(status),
+                request_id.dimmed().to_string(),
+            )
+        } else {
+            (request_line, status.to_string(), request_id)
+        };
+        format!(
+            r#"{} - "{request_line}" - {status} {}B {:?}{request_id}"#,
+            exchange.peer,
+            exchange.bytes_written,
+            exchange.duration(),
+        )
+    }
+
+    fn json(exchange: &Exchange<'_>) -> String {
+        let request = exchange.request;
+        let response = exchange.response;
+        let field = |value: Option<&SmolStr>| json_or_null(value);
+        let status = &response.status_code;
+        let status = if !status.is_empty() && status.bytes().all(|byte| byte.is_ascii_digit()) {
+            status.to_string()
+        } else {
+            json_string(status)
+        };
+        format!(
+            concat!(
+                r#"{{"time":{},"remote":{},"method":{},"target":{},"protocol":{},"status":{},"bytes":{},"#,
+                r#""bytes_written":{},"duration_us":{},"handling_us":{},"referer":{},"user_agent":{},"#,
+                r#""request_id":{}}}"#
+            ),
+            json_string(&Timestamp::new(exchange.received).rfc3339()),
+            json_string(&exchange.peer.ip().to_string()),
+            field(request.map(|request| &request.method)),
+            field(request.map(|request| &request.target)),
+            field(request.map(|request| &request.version)),
+            status,
+            response.body.len(),
+            exchange.bytes_written,
+            exchange.duration().as_micros(),
+            // timed by `RequestIdMiddleware`, so only the handlers after it are counted
+            RequestIdMiddleware::duration(response)
+                .map_or_else(|| "null".to_owned(), |duration| duration.as_micros().to_string()),
+            field(request.and_then(|request| request.headers.get("Referer"))),
+            field(request.and_then(|request| request.headers.get("User-Agent"))),
+            field(response.headers.get(RequestIdMiddleware::HEADER)),
+        )
+    }
+}
+
+/// A log file which is renamed to `<path>.1` once it reaches `max_size`, shifting older ones up to `<path>.<keep>`.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    /// `None` never rotates.
+    max_size: Option<u64>,
+    /// Rotated files to keep; `0` truncates the log instead.
+    keep: usize,
+}
+
+impl RotatingFile {
+    /// Opens the file for appending, creating it if needed.
+    #[inline]
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            size: file.metadata()?.len(),
+            path,
+            file,
+            max_size: None,
+            keep: 5,
+        })
+    }
+
+    #[inline]
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    #[inline]
+    pub fn with_keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for index in (1..self.keep).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index.saturating_add(1)))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = u64::try_from(buf.len()).unwrap_or(u64::MAX);
+        if let Some(max_size) = self.max_size
+            && self.size > 0
+            && self.size.saturating_add(len) > max_size
+        {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size = self.size.saturating_add(u64::try_from(written).unwrap_or(u64::MAX));
+        Ok(written)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// How many lines may wait for the writer thread before logging waits for it to catch up.
+const QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug)]
+enum Command {
+    Line(String),
+    /// Answered once the lines queued before are written and the output flushed.
+    Flush(oneshot::Sender<()>),
+}
+
+/// Writes a line per response to stderr, a file or any writer.
+///
+/// Lines are written on a thread of their own, so a slow disk or a blocked stderr does not hold up the runtime; once
+/// [`QUEUE_SIZE`] of them wait, logging waits as well. Dropping the log waits for what is queued to be written. Lines
+/// are coloured only when written to a terminal, unless told otherwise with [`AccessLog::with_color`].
+pub struct AccessLog {
+    format: LogFormat,
+    /// Taken on drop, which stops the writer once it wrote what was queued.
+    lines: Option<mpsc::Sender<Command>>,
+    writer: Option<JoinHandle<()>>,
+    colorize: bool,
+}
+
+impl Debug for AccessLog {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .field("colorize", &self.colorize)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for AccessLog {
+    #[inline]
+    fn default() -> Self {
+        Self::stderr()
+    }
+}
+
+impl Drop for AccessLog {
+    #[inline]
+    fn drop(&mut self) {
+        drop(self.lines.take());
+        if let Some(writer) = self.writer.take()
+            && writer.join().is_err()
+        {
+            eprintln!("failed to write the access log: the writer panicked");
+        }
+    }
+}
+
+impl AccessLog {
+    #[inline]
+    pub fn stderr() -> Self {
+        Self::new(io::stderr(), io::stderr().is_terminal())
+    }
+
+    /// Logs to `writer`, such as a [`RotatingFile`], without colour.
+    #[inline]
+    pub fn writer(writer: impl Write + Send + 'static) -> Self {
+        Self::new(writer, false)
+    }
+
+    fn new(output: impl Write + Send + 'static, colorize: bool) -> Self {
+        let (lines, writer) = spawn_writer(output);
+        Self {
+            format: LogFormat::default(),
+            lines: Some(lines),
+            writer,
+            colorize,
+        }
+    }
+
+    #[inline]
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    #[inline]
+    pub fn with_color(mut self, colorize: bool) -> Self {
+        self.colorize = colorize;
+        self
+    }
+
+    /// Waits until the lines logged so far are written and the output flushed.
+    #[inline]
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if let Some(lines) = self.lines.as_ref()
+            && lines.send(Command::Flush(done)).await.is_ok()
+        {
+            drop(flushed.await);
+        }
+    }
+}
+
+/// Starts the thread writing the lines sent to it to `output`, until the sender is dropped.
+fn spawn_writer(output: impl Write + Send + 'static) -> (mpsc::Sender<Command>, Option<JoinHandle<()>>) {
+    let (lines, received) = mpsc::channel(QUEUE_SIZE);
+    let spawned = thread::Builder::new()
+        .name("access-log".to_owned())
+        .spawn(move || write_lines(output, received));
+    match spawned {
+        Ok(writer) => (lines, Some(writer)),
+        Err(error) => {
+            eprintln!("failed to start the access log: {error}");
+            (lines, None)
+        }
+    }
+}
+
+fn write_lines(mut output: impl Write, mut commands: mpsc::Receiver<Command>) {
+    while let Some(command) = commands.blocking_recv() {
+        let mut flushes = Vec::new();
+        // flush once for all the lines that queued up meanwhile
+        let written = std::iter::once(command)
+            .chain(std::iter::from_fn(|| commands.try_recv().ok()))
+            .try_for_each(|queued| match queued {
+                Command::Line(line) => output.write_all(line.as_bytes()),
+                Command::Flush(done) => {
+                    flushes.push(done);
+                    Ok(())
+                }
+            })
+            .and_then(|()| output.flush());
+        if let Err(error) = written {
+            eprintln!("failed to write the access log: {error}");
+        }
+        for done in flushes {
+            // the waiter may have given up
+            done.send(()).unwrap_or_default();
+        }
+    }
+}
+
+#[async_trait]
+impl Observer for AccessLog {
+    #[inline]
+    async fn observe(&self, exchange: &Exchange<'_>) {
+        let mut line = self.format.render(exchange, self.colorize);
+        line.push('\n');
+        let sent = match self.lines.as_ref() {
+            Some(lines) => lines.send(Command::Line(line)).await.is_ok(),
+            None => false,
+        };
+        if !sent {
+            eprintln!("failed to write the access log: it is not running");
+        }
+    }
+}
@@ -1,29 +1,71 @@
 use std::borrow::Cow;
-use std::io::{Error, Write as _};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
 
 use async_trait::async_trait;
 use flate2::Compression;
-use flate2::write::{DeflateDecoder, DeflateEncoder, GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+use smol_str::SmolStr;
+use tokio::io::{AsyncWrite, AsyncWriteExt as _};
 
 use crate::http::{Request, Response};
 use crate::middleware::{Middleware, Next};
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
-fn try_compress(encoding: &str, data: &[u8]) -> Result<Option<Vec<u8>>> {
+#[derive(Debug, Clone, Copy)]
+pub struct Levels {
+    pub gzip: u32,
+    pub deflate: u32,
+    pub zlib: u32,
+    /// Brotli quality, from 0 to 11.
+    pub brotli: u32,
+    /// Zstandard level, from -7 to 22.
+    pub zstd: i32,
+}
+
+impl Default for Levels {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            gzip: 6,
+            deflate: 6,
+            zlib: 6,
+            brotli: 4,
+            zstd: 3,
+        }
+    }
+}
+
+/// Compresses `data` with `encoding`, or returns `None` for a coding this build does not support.
+#[inline]
+pub fn try_compress(encoding: &str, data: &[u8], levels: &Levels) -> Result<Option<Vec<u8>>> {
     Ok(match encoding {
         "gzip" => {
-            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::new(levels.gzip));
             encoder.write_all(data)?;
             Some(encoder.finish()?)
         }
         "deflate" => {
-            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(levels.deflate));
             encoder.write_all(data)?;
             Some(encoder.finish()?)
         }
         "zlib" => {
-            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(levels.zlib));
+            encoder.write_all(data)?;
+            Some(encoder.finish()?)
+        }
+        #[cfg(feature = "brotli")]
+        "br" => {
+            let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, levels.brotli, 22);
+            encoder.write_all(data)?;
+            Some(encoder.into_inner())
+        }
+        #[cfg(feature = "zstd")]
+        "zstd" => {
+            let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), levels.zstd)?;
             encoder.write_all(data)?;
             Some(encoder.finish()?)
         }
@@ -31,29 +73,248 @@ fn try_compress(encoding: &str, data: &[u8]) -> Result<Option<Vec<u8>>> {
     })
 }
 
-fn try_decompress(encoding: &str, data: &[u8]) -> Result<Option<Vec<u8>>> {
-    Ok(match encoding {
-        "gzip" => {
-            let mut decoder = GzDecoder::new(Vec::new());
-            decoder.write_all(data)?;
-            Some(decoder.finish()?)
-        }
-        "deflate" => {
-            let mut decoder = DeflateDecoder::new(Vec::new());
-            decoder.write_all(data)?;
-            Some(decoder.finish()?)
+/// A growable buffer which fails with [`ErrorKind::FileTooLarge`] once more than `limit` bytes are written into it,
+/// so decoders writing into it abort as soon as the output gets too large.
+struct LimitedBuffer {
+    buf: Vec<u8>,
+    limit: usize,
+}
+
+impl LimitedBuffer {
+    fn new(limit: usize) -> Self {
+        Self { buf: Vec::new(), limit }
+    }
+}
+
+impl Write for LimitedBuffer {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.buf.len() + buf.len() > self.limit {
+            return Err(Error::new(
+                ErrorKind::FileTooLarge,
+                "decompressed body exceeds the limit",
+            ));
         }
-        "zlib" => {
-            let mut decoder = ZlibDecoder::new(Vec::new());
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads `decoder` to the end into a [`LimitedBuffer`]; unlike writing decoders, reading ones fail on a stream cut
+/// short.
+fn decode(mut decoder: impl Read, limit: usize) -> Result<Vec<u8>> {
+    let mut decompressed = LimitedBuffer::new(limit);
+    std::io::copy(&mut decoder, &mut decompressed)?;
+    Ok(decompressed.buf)
+}
+
+/// Decompresses `data` with `encoding`, failing with [`ErrorKind::FileTooLarge`] once the output exceeds `limit`, or
+/// returns `None` for a coding this build does not support.
+#[inline]
+pub fn try_decompress(encoding: &str, data: &[u8], limit: usize) -> Result<Option<Vec<u8>>> {
+    Ok(match encoding {
+        "gzip" => Some(decode(GzDecoder::new(data), limit)?),
+        "deflate" => Some(decode(DeflateDecoder::new(data), limit)?),
+        "zlib" => Some(decode(ZlibDecoder::new(data), limit)?),
+        #[cfg(feature = "brotli")]
+        "br" => {
+            let mut decoder = brotli::DecompressorWriter::new(LimitedBuffer::new(limit), 4096);
             decoder.write_all(data)?;
-            Some(decoder.finish()?)
+            let decompressed = decoder
+                .into_inner()
+                .map_err(|_truncated| Error::new(ErrorKind::UnexpectedEof, "truncated brotli stream"))?;
+            Some(decompressed.buf)
         }
+        #[cfg(feature = "zstd")]
+        "zstd" => Some(decode(zstd::stream::read::Decoder::with_buffer(data)?, limit)?),
         _ => None,
     })
 }
 
+/// Yields the codings listed in an `Accept-Encoding` header, dropping parameters and refused (`q=0`) ones.
+fn accepted_encodings(header: &str) -> impl Iterator<Item = &str> {
+    header.split(",").filter_map(|item| {
+        let mut params = item.split(";").map(str::trim);
+        let encoding = params.next()?;
+        let refused = params.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+        (!encoding.is_empty() && !refused).then_some(encoding)
+    })
+}
+
+/// Media types which are already compressed and not worth compressing again.
+pub const INCOMPRESSIBLE_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "video/",
+    "audio/",
+    "font/woff",
+    "application/zip",
+    "application/gzip",
+    "application/zstd",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/octet-stream",
+];
+
 pub struct CompressMiddleware {
     pub min_size: usize,
+    pub levels: Levels,
+    /// Largest request body accepted after decompression.
+    pub max_decompressed_size: usize,
+    /// Largest accepted ratio between the decompressed and the compressed request body.
+    pub max_ratio: usize,
+    /// Media type prefixes to compress; an empty list allows every type.
+    pub allow: Vec<SmolStr>,
+    /// Media type prefixes never to compress, checked before `allow`.
+    pub deny: Vec<SmolStr>,
+}
+
+impl Default for CompressMiddleware {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            min_size: 1024,
+            levels: Levels::default(),
+            max_decompressed_size: 16 * 1024 * 1024,
+            max_ratio: 100,
+            allow: Vec::new(),
+            deny: INCOMPRESSIBLE_TYPES.iter().copied().map(SmolStr::new_static).collect(),
+        }
+    }
+}
+
+impl CompressMiddleware {
+    #[inline]
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    #[inline]
+    pub fn with_levels(mut self, levels: Levels) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    #[inline]
+    pub fn with_max_decompressed_size(mut self, max_decompressed_size: usize) -> Self {
+        self.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    #[inline]
+    pub fn with_max_ratio(mut self, max_ratio: usize) -> Self {
+        self.max_ratio = max_ratio;
+        self
+    }
+
+    #[inline]
+    pub fn allow(mut self, content_type: impl Into<SmolStr>) -> Self {
+        self.allow.push(content_type.into());
+        self
+    }
+
+    #[inline]
+    pub fn deny(mut self, content_type: impl Into<SmolStr>) -> Self {
+        self.deny.push(content_type.into());
+        self
+    }
+
+    fn should_compress(&self, response: &Response) -> bool {
+        if response.headers.get("Content-Encoding").is_some() {
+            return false;
+        }
+        if let Some(cache_control) = response.headers.get("Cache-Control")
+            && cache_control
+                .split(",")
+                .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+        {
+            return false;
+        }
+        let media_type = response
+            .headers
+            .get("Content-Type")
+            .and_then(|content_type| content_type.split(";").next())
+            .map(|media_type| media_type.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        if self.deny.iter().any(|prefix| media_type.starts_with(prefix.as_str())) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|prefix| media_type.starts_with(prefix.as_str()))
+    }
+
+    /// Picks the coding to apply to `response`, or `None` if it should be sent as is, as are responses which cannot
+    /// have a body: those to `HEAD`, `1xx` (such as a WebSocket upgrade), `204 No Content` and `304 Not Modified`.
+    ///
+    /// Streamed responses (chunked or `text/event-stream`) are negotiated regardless of `min_size`, since their body
+    /// is written later through a [`BodyWriter`], but only while their body is empty: one already attached would be
+    /// sent uncompressed ahead of it.
+    #[inline]
+    pub fn negotiate<'r>(&self, request: &'r Request, response: &Response) -> Option<&'r str> {
+        let unfit = if is_streaming(response) {
+            !response.body.is_empty()
+        } else {
+            response.body.len() < self.min_size
+        };
+        if unfit || is_bodiless(request, response) || !self.should_compress(response) {
+            return None;
+        }
+        accepted_encodings(request.headers.get("Accept-Encoding")?).find(|encoding| is_supported(encoding))
+    }
+}
+
+fn is_supported(encoding: &str) -> bool {
+    match encoding {
+        "gzip" | "deflate" | "zlib" => true,
+        #[cfg(feature = "brotli")]
+        "br" => true,
+        #[cfg(feature = "zstd")]
+        "zstd" => true,
+        _ => false,
+    }
+}
+
+/// Adds `Accept-Encoding` to the `Vary` header, keeping whatever the handler listed there.
+fn vary_on_accept_encoding(mut response: Response) -> Response {
+    let already = response.headers.get("Vary").is_some_and(|vary| {
+        vary.split(",")
+            .map(str::trim)
+            .any(|field| field == "*" || field.eq_ignore_ascii_case("Accept-Encoding"))
+    });
+    if !already {
+        response.headers.append("Vary", "Accept-Encoding");
+    }
+    response
+}
+
+fn is_bodiless(request: &Request, response: &Response) -> bool {
+    request.method == "HEAD"
+        || response
+            .status_code
+            .parse::<u16>()
+            .is_ok_and(|status| matches!(status, 100..=199 | 204 | 304))
+}
+
+fn is_streaming(response: &Response) -> bool {
+    response
+        .headers
+        .get("Transfer-Encoding")
+        .is_some_and(|codings| codings.split(",").any(|coding| coding.trim() == "chunked"))
+        || response
+            .headers
+            .get("Content-Type")
+            .is_some_and(|content_type| content_type.starts_with("text/event-stream"))
 }
 
 #[async_trait]
@@ -62,29 +323,273 @@ impl<E: From<Error>> Middleware<E> for CompressMiddleware {
     async fn call(&self, request: &Request, next: &dyn Next<E>) -> Result<Response, E> {
         let mut request = Cow::Borrowed(request);
 
-        if let Some(encoding) = request.headers.get("Content-Encoding")
-            && let Some(decompressed) = try_decompress(encoding, &request.body)?
-        {
-            let length = decompressed.len().to_string();
-            let mut owned = request.into_owned();
-            owned.body = decompressed;
-            owned.headers.remove("Content-Encoding");
-            owned.headers.insert("Content-Length", length);
-            request = Cow::Owned(owned);
+        if let Some(encoding) = request.headers.get("Content-Encoding") {
+            let limit = self
+                .max_decompressed_size
+                .min(request.body.len().saturating_mul(self.max_ratio));
+            match try_decompress(encoding, &request.body, limit) {
+                Ok(Some(decompressed)) => {
+                    let length = decompressed.len().to_string();
+                    let mut owned = request.into_owned();
+                    owned.body = decompressed;
+                    owned.headers.remove("Content-Encoding");
+                    owned.headers.insert("Content-Length", length);
+                    request = Cow::Owned(owned);
+                }
+                Ok(None) => {}
+                Err(e) if e.kind() == ErrorKind::FileTooLarge => {
+                    return Ok(Response::new(413, "Payload Too Large").plain(e.to_string()));
+                }
+                Err(e) => return Ok(Response::new(400, "Bad Request").plain(e.to_string())),
+            }
         }
 
         let mut response = next.call(&request).await?;
 
-        if response.body.len() >= self.min_size
+        if let Some(encoding) = self.negotiate(&request, &response) {
+            if !is_streaming(&response)
+                && let Some(compressed) = try_compress(encoding, &response.body, &self.levels)?
+            {
+                response.body = compressed;
+            }
+            return Ok(vary_on_accept_encoding(
+                response.with_header("Content-Encoding", encoding),
+            ));
+        }
+        Ok(response)
+    }
+}
+
+/// An incremental encoder which flushes its output after every chunk, for bodies that are not known upfront.
+pub enum StreamEncoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(DeflateEncoder<Vec<u8>>),
+    Zlib(ZlibEncoder<Vec<u8>>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl StreamEncoder {
+    #[inline]
+    pub fn new(encoding: &str, levels: &Levels) -> Result<Option<Self>> {
+        Ok(match encoding {
+            "gzip" => Some(Self::Gzip(GzEncoder::new(Vec::new(), Compression::new(levels.gzip)))),
+            "deflate" => Some(Self::Deflate(DeflateEncoder::new(
+                Vec::new(),
+                Compression::new(levels.deflate),
+            ))),
+            "zlib" => Some(Self::Zlib(ZlibEncoder::new(Vec::new(), Compression::new(levels.zlib)))),
+            #[cfg(feature = "brotli")]
+            "br" => Some(Self::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                levels.brotli,
+                22,
+            )))),
+            #[cfg(feature = "zstd")]
+            "zstd" => Some(Self::Zstd(zstd::stream::write::Encoder::new(Vec::new(), levels.zstd)?)),
+            _ => None,
+        })
+    }
+
+    #[expect(clippy::pattern_type_mismatch, reason = "binding the encoder by reference")]
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Self::Gzip(encoder) => encoder,
+            Self::Deflate(encoder) => encoder,
+            Self::Zlib(encoder) => encoder,
+            #[cfg(feature = "brotli")]
+            Self::Brotli(encoder) => encoder,
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder,
+        }
+    }
+
+    #[expect(clippy::pattern_type_mismatch, reason = "binding the encoder by reference")]
+    fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            Self::Gzip(encoder) => encoder.get_mut(),
+            Self::Deflate(encoder) => encoder.get_mut(),
+            Self::Zlib(encoder) => encoder.get_mut(),
+            #[cfg(feature = "brotli")]
+            Self::Brotli(encoder) => encoder.get_mut(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.get_mut(),
+        }
+    }
+
+    /// Compresses `chunk` and returns everything the encoder has produced so far, flushed to a byte boundary.
+    #[inline]
+    pub fn encode(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        let writer = self.writer();
+        writer.write_all(chunk)?;
+        writer.flush()?;
+        Ok(std::mem::take(self.output()))
+    }
+
+    /// Terminates the stream and returns the trailing bytes.
+    #[inline]
+    pub fn finish(self) -> Result<Vec<u8>> {
+        match self {
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Deflate(encoder) => encoder.finish(),
+            Self::Zlib(encoder) => encoder.finish(),
+            #[cfg(feature = "brotli")]
+            Self::Brotli(encoder) => Ok(encoder.into_inner()),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// Writes a streamed response body after its head has been sent, compressing it according to the response's
+/// `Content-Encoding` and framing it according to its `Transfer-Encoding`.
+pub struct BodyWriter<W> {
+    writer: W,
+    encoder: Option<StreamEncoder>,
+    chunked: bool,
+}
+
+impl<W: AsyncWrite + Unpin> BodyWriter<W> {
+    #[inline]
+    pub fn new(writer: W, response: &Response, levels: &Levels) -> Result<Self> {
+        let encoder = match response.headers.get("Content-Encoding") {
+            Some(encoding) => StreamEncoder::new(encoding, levels)?,
+            None => None,
+        };
+        let chunked = response
+            .headers
+            .get("Transfer-Encoding")
+            .is_some_and(|codings| codings.split(",").any(|coding| coding.trim() == "chunked"));
+        Ok(Self {
+            writer,
+            encoder,
+            chunked,
+        })
+    }
+
+    async fn write_raw(&mut self, data: &[u8]) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.chunked {
+            self.writer
+                .write_all(format!("{:x}\r\n", data.len()).as_bytes())
+                .await?;
+            self.writer.write_all(data).await?;
+            self.writer.write_all(b"\r\n").await?;
+        } else {
+            self.writer.write_all(data).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes one chunk of the body and flushes it to the client.
+    #[inline]
+    pub async fn send(&mut self, data: &[u8]) -> Result<()> {
+        match self.encoder.as_mut() {
+            Some(encoder) => {
+                let encoded = encoder.encode(data)?;
+                self.write_raw(&encoded).await?;
+            }
+            None => self.write_raw(data).await?,
+        }
+        self.writer.flush().await
+    }
+
+    /// Ends the body and gives the underlying writer back.
+    #[inline]
+    pub async fn finish(mut self) -> Result<W> {
+        if let Some(encoder) = self.encoder.take() {
+            let trailing = encoder.finish()?;
+            self.write_raw(&trailing).await?;
+        }
+        if self.chunked {
+            self.writer.write_all(b"0\r\n\r\n").await?;
+        }
+        self.writer.flush().await?;
+        Ok(self.writer)
+    }
+}
+
+/// Guesses the `Content-Type` of a static file from its extension.
+#[inline]
+pub fn guess_content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Serves `<file>.br` / `<file>.gz` siblings of static files under `root`, so they are not compressed at request time.
+///
+/// Requests without a matching sibling, or whose `Accept-Encoding` refuses it, fall through to the next handler.
+pub struct PrecompressedMiddleware {
+    pub root: PathBuf,
+    pub endpoint: SmolStr,
+    /// Sibling extensions in order of preference, with the coding each one carries.
+    pub siblings: Vec<(SmolStr, SmolStr)>,
+}
+
+impl PrecompressedMiddleware {
+    #[inline]
+    pub fn new(root: impl Into<PathBuf>, endpoint: impl Into<SmolStr>) -> Self {
+        Self {
+            root: root.into(),
+            endpoint: endpoint.into(),
+            siblings: vec![("br".into(), "br".into()), ("gz".into(), "gzip".into())],
+        }
+    }
+
+    #[inline]
+    pub fn with_sibling(mut self, extension: impl Into<SmolStr>, encoding: impl Into<SmolStr>) -> Self {
+        self.siblings.push((extension.into(), encoding.into()));
+        self
+    }
+
+    fn resolve(&self, target: &str) -> Option<PathBuf> {
+        let target = target.split_once("?").map_or(target, |(path, _)| path);
+        let relative = Path::new(target.strip_prefix(self.endpoint.as_str())?);
+        relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+            .then(|| self.root.join(relative))
+    }
+}
+
+#[async_trait]
+impl<E: From<Error>> Middleware<E> for PrecompressedMiddleware {
+    #[inline]
+    async fn call(&self, request: &Request, next: &dyn Next<E>) -> Result<Response, E> {
+        if let Some(path) = self.resolve(&request.target)
+            && matches!(request.method.as_str(), "GET" | "HEAD")
             && let Some(encodings) = request.headers.get("Accept-Encoding")
         {
-            for encoding in encodings.split(",").map(str::trim) {
-                if let Some(compressed) = try_compress(encoding, &response.body)? {
-                    response.body = compressed;
-                    return Ok(response.with_header("Content-Encoding", encoding));
+            for (extension, encoding) in self.siblings.iter().cloned() {
+                if !accepted_encodings(encodings).any(|accepted| accepted == encoding) {
+                    continue;
+                }
+                let mut sibling = path.clone().into_os_string();
+                sibling.push(".");
+                sibling.push(extension.as_str());
+                if let Ok(body) = tokio::fs::read(&sibling).await {
+                    let response = Response::ok()
+                        .with_header("Content-Type", guess_content_type(&path))
+                        .with_header("Content-Encoding", encoding)
+                        .with_body(body);
+                    return Ok(vary_on_accept_encoding(response));
                 }
             }
         }
-        Ok(response)
+        next.call(request).await
     }
 }
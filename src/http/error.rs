@@ -10,6 +10,8 @@ pub enum Error {
     BadContentLength(ParseIntError),
     #[error("Content-Length is required")]
     ContentLengthRequired,
+    #[error("Content-Length of {0} exceeds the limit")]
+    PayloadTooLarge(usize),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
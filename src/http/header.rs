@@ -19,14 +19,35 @@ impl HeaderMap {
         self.inner.insert(key.to_smolstr(), value.to_smolstr());
     }
 
+    /// Looks up a header, falling back to a case-insensitive match since peers are free to choose the case.
     #[inline]
     pub fn get(&self, key: impl ToSmolStr) -> Option<&SmolStr> {
-        self.inner.get(&key.to_smolstr())
+        let key = key.to_smolstr();
+        self.inner.get(&key).or_else(|| {
+            self.inner
+                .iter()
+                .find_map(|(name, value)| name.eq_ignore_ascii_case(&key).then_some(value))
+        })
     }
 
+    /// Adds `value` to the comma-separated list of a header, in whatever case it was sent, or inserts it.
+    #[inline]
+    pub fn append(&mut self, key: impl ToSmolStr, value: impl ToSmolStr) {
+        let key = key.to_smolstr();
+        let value = value.to_smolstr();
+        match self.inner.iter_mut().find(|entry| entry.0.eq_ignore_ascii_case(&key)) {
+            Some((_, existing)) => *existing = format!("{existing}, {value}").into(),
+            None => {
+                self.inner.insert(key, value);
+            }
+        }
+    }
+
+    /// Removes a header in whatever case it was sent.
     #[inline]
     pub fn remove(&mut self, key: impl ToSmolStr) {
-        self.inner.remove(&key.to_smolstr());
+        let key = key.to_smolstr();
+        self.inner.retain(|name, _| !name.eq_ignore_ascii_case(&key));
     }
 }
 
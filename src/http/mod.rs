@@ -7,7 +7,7 @@ mod target;
 pub use error::{Error, Result};
 pub use header::HeaderMap;
 pub use request::Request;
-pub use response::Response;
+pub use response::{Response, Timing};
 pub use target::Target;
 use tokio::io::{AsyncBufRead, AsyncBufReadExt as _};
 
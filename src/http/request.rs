@@ -16,8 +16,20 @@ pub struct Request {
 }
 
 impl Request {
+    /// The largest body [`Request::try_from_buf_async`] reads.
+    pub const MAX_BODY_SIZE: usize = 16 << 20;
+
     #[inline]
-    pub async fn try_from_buf_async(mut reader: impl AsyncBufRead + Unpin) -> Result<Self> {
+    pub async fn try_from_buf_async(reader: impl AsyncBufRead + Unpin) -> Result<Self> {
+        Self::try_from_buf_async_with_limit(reader, Self::MAX_BODY_SIZE).await
+    }
+
+    /// Reads a request, refusing a body declared larger than `max_body_size` before reading any of it.
+    #[inline]
+    pub async fn try_from_buf_async_with_limit(
+        mut reader: impl AsyncBufRead + Unpin,
+        max_body_size: usize,
+    ) -> Result<Self> {
         let request_line = read_line(&mut reader).await?;
         let [method, target, version] = request_line.split(" ").collect::<Vec<_>>()[..] else {
             return Err(Error::BadRequestLine(request_line));
@@ -37,9 +49,14 @@ impl Request {
             Some(length) => length.parse::<usize>().map_err(Error::BadContentLength)?,
             None => 0,
         };
-        let mut body = Vec::with_capacity(length);
-        if length != 0 {
-            reader.read_exact(&mut body).await?;
+        if length > max_body_size {
+            return Err(Error::PayloadTooLarge(length));
+        }
+        // grows with what actually arrives instead of trusting the declared length
+        let mut body = Vec::new();
+        let limit = u64::try_from(length).unwrap_or(u64::MAX);
+        if (&mut reader).take(limit).read_to_end(&mut body).await? < length {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
         }
 
         Ok(Self {
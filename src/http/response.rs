@@ -1,9 +1,12 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result};
+use std::time::{Duration, SystemTime};
 
 use smol_str::{SmolStr, ToSmolStr};
 
+use crate::BoxError;
 use crate::http::HeaderMap;
+use crate::upgrade::{OnUpgrade, Upgraded};
 
 #[derive(Debug, Clone)]
 pub struct Response {
@@ -12,6 +15,25 @@ pub struct Response {
     pub description: SmolStr,
     pub headers: HeaderMap,
     pub body: Vec<u8>,
+    /// Takes over the connection after this response is written, whatever its status.
+    pub upgrade: Option<OnUpgrade>,
+    /// How long handling the request took, for services and observers; never written to the client.
+    pub timing: Option<Timing>,
+}
+
+/// When handling a request started and ended, as measured by a middleware such as
+/// [`RequestIdMiddleware`](crate::middleware::RequestIdMiddleware).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+impl Timing {
+    #[inline]
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
 }
 
 impl Response {
@@ -23,6 +45,8 @@ impl Response {
             description: description.to_smolstr(),
             headers: HeaderMap::new(),
             body: Vec::new(),
+            upgrade: None,
+            timing: None,
         }
     }
 
@@ -38,6 +62,16 @@ impl Response {
         self
     }
 
+    #[inline]
+    pub fn with_upgrade<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Upgraded) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<(), BoxError>> + Send + 'static,
+    {
+        self.upgrade = Some(OnUpgrade::new(handler));
+        self
+    }
+
     #[inline]
     pub fn response_line(&self) -> String {
         format!("{} {} {}", self.version, self.status_code, self.description)
@@ -29,11 +29,14 @@
 #![allow(clippy::std_instead_of_core, reason = "..")]
 #![allow(clippy::use_debug, reason = "..")]
 
+pub mod access_log;
 pub mod compress;
 pub mod http;
 pub mod middleware;
 pub mod server;
 pub mod service;
+pub mod sse;
+pub mod upgrade;
 pub mod websocket;
 
 use std::error::Error;
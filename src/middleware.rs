@@ -1,8 +1,11 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
+use smol_str::SmolStr;
 
-use crate::http::{Request, Response};
+use crate::http::{Request, Response, Timing};
 
 #[async_trait]
 pub trait Next<E>: Send + Sync {
@@ -84,3 +87,132 @@ impl<E> Middleware<E> for ContentLengthMiddleware {
         }
     }
 }
+
+/// Tags every request with an ID, so the log lines and responses belonging to it can be correlated, and times it.
+///
+/// Later middleware and handlers get the ID and the start time from [`RequestIdMiddleware::id`] and
+/// [`RequestIdMiddleware::start`]. The response carries the ID in its `X-Request-Id` header, and the time spent in the
+/// rest of the chain in [`Response::timing`] for services and observers; only with
+/// [`RequestIdMiddleware::with_server_timing`] is that time also added to its `Server-Timing` header.
+#[derive(Debug, Default, Clone)]
+pub struct RequestIdMiddleware {
+    /// Keep an `X-Request-Id` sent by the client, such as one set by a trusted proxy, instead of assigning one.
+    pub trust_incoming: bool,
+    /// Tell the client how long the request took in the `Server-Timing` header.
+    pub server_timing: bool,
+}
+
+/// The request being handled by the rest of the chain.
+#[derive(Debug)]
+struct Tagged {
+    id: SmolStr,
+    start: SystemTime,
+}
+
+tokio::task_local! {
+    static TAGGED: Tagged;
+}
+
+impl RequestIdMiddleware {
+    pub const HEADER: &str = "X-Request-Id";
+    /// The longest incoming ID kept when trusted.
+    pub const MAX_LEN: usize = 128;
+
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn with_trust_incoming(mut self, trust_incoming: bool) -> Self {
+        self.trust_incoming = trust_incoming;
+        self
+    }
+
+    #[inline]
+    pub fn with_server_timing(mut self, server_timing: bool) -> Self {
+        self.server_timing = server_timing;
+        self
+    }
+
+    /// The ID of the request being handled, when called from the rest of the chain.
+    #[inline]
+    pub fn id() -> Option<SmolStr> {
+        TAGGED.try_with(|tagged| tagged.id.clone()).ok()
+    }
+
+    /// When the request being handled started, when called from the rest of the chain.
+    #[inline]
+    pub fn start() -> Option<SystemTime> {
+        TAGGED.try_with(|tagged| tagged.start).ok()
+    }
+
+    /// When the rest of the chain finished handling a request, as seen on its response.
+    #[inline]
+    pub fn end(response: &Response) -> Option<SystemTime> {
+        Some(response.timing?.end)
+    }
+
+    /// How long the rest of the chain took to handle a request, as seen on its response.
+    #[inline]
+    pub fn duration(response: &Response) -> Option<Duration> {
+        Some(response.timing?.duration())
+    }
+
+    fn incoming_id(&self, request: &Request) -> Option<SmolStr> {
+        // only printable ASCII, so a client cannot forge log lines with it
+        request
+            .headers
+            .get(Self::HEADER)
+            .filter(|id| self.trust_incoming && !id.is_empty() && id.len() <= Self::MAX_LEN)
+            .filter(|id| id.bytes().all(|byte| byte.is_ascii_graphic()))
+            .cloned()
+    }
+}
+
+/// A random 128-bit ID in hex, or one made of the time and a counter should randomness be unavailable.
+fn generate_id() -> SmolStr {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut bytes = [0_u8; 16];
+    if getrandom::fill(&mut bytes).is_err() {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        bytes = (nanos ^ u128::from(count)).to_be_bytes();
+    }
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[async_trait]
+impl<E> Middleware<E> for RequestIdMiddleware {
+    #[inline]
+    async fn call(&self, request: &Request, next: &dyn Next<E>) -> Result<Response, E> {
+        let start = SystemTime::now();
+        let timer = Instant::now();
+        let id = self.incoming_id(request).unwrap_or_else(generate_id);
+
+        // handed to the rest of the chain beside the request, which is not copied to carry them
+        let tagged = Tagged { id: id.clone(), start };
+        let mut response = TAGGED.scope(tagged, next.call(request)).await?;
+
+        let elapsed = timer.elapsed();
+        // measured on the monotonic clock, so the end cannot come before the start
+        let end = start.checked_add(elapsed).unwrap_or(start);
+        response.headers.remove(Self::HEADER);
+        response.headers.insert(Self::HEADER, id);
+        response.timing = Some(Timing { start, end });
+        if self.server_timing {
+            response.headers.append(
+                "Server-Timing",
+                format!(
+                    "total;dur={}.{:03}",
+                    elapsed.as_millis(),
+                    elapsed.subsec_micros() % 1000
+                ),
+            );
+        }
+        Ok(response)
+    }
+}
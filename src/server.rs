@@ -1,14 +1,18 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
-use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, AsyncWriteExt as _, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt as _, AsyncRead, AsyncWrite, AsyncWriteExt as _, BufReader};
 
 use crate::http::{self, Request, Response};
 use crate::middleware::{Middleware, MiddlewareChain, Next};
-use crate::service::{ConnectionFlag, Service};
+use crate::service::{Exchange, Observer, Service};
+use crate::upgrade::Upgraded;
 
 pub struct Server<E, S> {
+    max_body_size: usize,
     middlewares: MiddlewareChain<E>,
+    observers: Vec<Arc<dyn Observer>>,
     services: Vec<Arc<dyn Service<E, S>>>,
 }
 
@@ -16,17 +20,32 @@ impl<E, S> Server<E, S> {
     #[inline]
     pub fn new(next: impl Next<E> + 'static) -> Self {
         Self {
+            max_body_size: Request::MAX_BODY_SIZE,
             middlewares: MiddlewareChain::new(next),
+            observers: Vec::new(),
             services: Vec::new(),
         }
     }
 
+    /// Answers requests declaring a larger body with `413 Payload Too Large`, and closes their connection.
+    #[inline]
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
     #[inline]
     pub fn with_middleware(mut self, middleware: impl Middleware<E> + 'static) -> Self {
         self.middlewares.push(Arc::new(middleware));
         self
     }
 
+    #[inline]
+    pub fn with_observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
     #[inline]
     pub fn with_service(mut self, service: impl Service<E, S> + 'static) -> Self {
         self.services.push(Arc::new(service));
@@ -46,7 +65,7 @@ impl<E, S> Server<E, S> {
     where
         E: From<std::io::Error> + Send,
     {
-        match Request::try_from_buf_async(stream).await {
+        match Request::try_from_buf_async_with_limit(stream, self.max_body_size).await {
             Ok(request) => {
                 let response = self.middlewares.call(&request).await?;
                 Ok((Some(request), response))
@@ -63,28 +82,72 @@ impl<E, S> Server<E, S> {
                 let response = Response::new(411, "Length Required").plain("Content Length Required");
                 Ok((None, response))
             }
+            Err(http::Error::PayloadTooLarge(length)) => {
+                // the body is left unread, so the connection cannot carry another request
+                let response = Response::new(413, "Payload Too Large")
+                    .with_header("Connection", "close")
+                    .plain(format!("Payload Too Large: {length}"));
+                Ok((None, response))
+            }
             Err(http::Error::IO(e)) => Err(e.into()),
         }
     }
 
     #[inline]
-    pub async fn handle_connection(&'static self, mut stream: S, address: SocketAddr) -> Result<(), E>
+    pub async fn handle_connection(&'static self, stream: S, address: SocketAddr) -> Result<(), E>
     where
         E: From<std::io::Error> + Send,
-        S: AsyncRead + AsyncWrite + Unpin,
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
     {
+        let mut stream = BufReader::new(stream);
         loop {
-            let (request, response) = self.handle_request(BufReader::new(&mut stream)).await?;
-            stream.write_all(&response.to_bytes()).await?;
+            // wait for the next request before timing it, so idle keep-alive time is not counted
+            if stream.fill_buf().await?.is_empty() {
+                return Ok(());
+            }
+            let received = SystemTime::now();
+            let start = Instant::now();
+            let (request, response) = self.handle_request(&mut stream).await?;
+            let handling = start.elapsed();
+            let bytes = response.to_bytes();
+            stream.write_all(&bytes).await?;
             stream.flush().await?;
 
-            if let Some(request) = request {
-                for service in self.services.iter() {
-                    let flag = service.call(&request, &response, &address, &mut stream).await?;
-                    if let ConnectionFlag::Close = flag {
-                        return Ok(());
-                    }
-                }
+            let exchange = Exchange {
+                request: request.as_ref(),
+                response: &response,
+                peer: address,
+                received,
+                handling,
+                writing: start.elapsed().saturating_sub(handling),
+                bytes_written: bytes.len(),
+            };
+            for observer in self.observers.iter() {
+                observer.observe(&exchange).await;
+            }
+
+            if let Some(upgrade) = response.upgrade {
+                let buffered = stream.buffer().to_vec();
+                let upgraded = Upgraded::new(stream.into_inner(), buffered);
+                return upgrade
+                    .call(upgraded)
+                    .await
+                    .map_err(|error| std::io::Error::other(error).into());
+            }
+            if let Some(request) = request
+                && let Some(service) = self
+                    .services
+                    .iter()
+                    .find(|service| service.accepts(&request, &response))
+            {
+                return service.call(&request, &response, &address, &mut stream).await;
+            }
+            if response
+                .headers
+                .get("Connection")
+                .is_some_and(|connection| connection.eq_ignore_ascii_case("close"))
+            {
+                return Ok(());
             }
         }
     }
@@ -98,6 +161,6 @@ where
     fn default() -> Self {
         Self::new(Response::new(404, "Not Found"))
             .with_middleware(crate::middleware::ContentLengthMiddleware)
-            .with_service(crate::service::LoggerService)
+            .with_observer(crate::access_log::AccessLog::default())
     }
 }
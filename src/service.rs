@@ -1,53 +1,71 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
+use tokio::io::BufReader;
 
 use crate::http::{Request, Response};
 
-pub enum ConnectionFlag {
-    Continue,
-    Close,
+/// What observers learn about a request once its response has been written.
+#[derive(Debug, Clone, Copy)]
+pub struct Exchange<'a> {
+    /// `None` when the request could not be parsed.
+    pub request: Option<&'a Request>,
+    pub response: &'a Response,
+    pub peer: SocketAddr,
+    /// When the request started arriving.
+    pub received: SystemTime,
+    /// From the request starting to arrive to the response being ready.
+    pub handling: Duration,
+    /// Spent writing the response.
+    pub writing: Duration,
+    /// Size of the response as written, head included.
+    pub bytes_written: usize,
 }
 
-#[async_trait]
-pub trait Service<E, S>: Send + Sync {
-    async fn call(
-        &self,
-        request: &Request,
-        response: &Response,
-        address: &SocketAddr,
-        stream: &mut S,
-    ) -> Result<ConnectionFlag, E>;
+impl Exchange<'_> {
+    /// From the request starting to arrive to the response being written.
+    #[inline]
+    pub fn duration(&self) -> Duration {
+        self.handling.saturating_add(self.writing)
+    }
 }
 
-pub struct LoggerService;
+/// Sees every response once it has been written, for logging or metrics; it cannot touch the connection.
+///
+/// Observers run in the order they were added, before any [`Service`] takes the connection over.
+#[async_trait]
+pub trait Observer: Send + Sync {
+    async fn observe(&self, exchange: &Exchange<'_>);
+}
 
+/// Shares an observer with the server, to flush or inspect it meanwhile.
 #[async_trait]
-impl<E, S> Service<E, S> for LoggerService {
+impl<T> Observer for Arc<T>
+where
+    T: Observer + ?Sized,
+{
     #[inline]
+    async fn observe(&self, exchange: &Exchange<'_>) {
+        T::observe(self, exchange).await;
+    }
+}
+
+/// Takes over the connection after a response it accepts, such as an event stream.
+///
+/// Only the first service accepting a response is called, and the connection is closed once it returns. It gets the
+/// server's reader, so bytes the client sent past the request are not lost. A response carrying an upgrade is handed
+/// to it instead, and no service sees it.
+#[async_trait]
+pub trait Service<E, S>: Send + Sync {
+    fn accepts(&self, request: &Request, response: &Response) -> bool;
+
     async fn call(
         &self,
         request: &Request,
         response: &Response,
-        address: &SocketAddr,
-        _: &mut S,
-    ) -> Result<ConnectionFlag, E> {
-        use colored::Colorize as _;
-        eprintln!(
-            r#"{} - "{}" - {}"#,
-            address,
-            request.request_line().bright_cyan(),
-            response
-                .status_code
-                .color(match response.status_code.to_string().chars().next() {
-                    Some('1') => "cyan",
-                    Some('2') => "green",
-                    Some('3') => "yellow",
-                    Some('4') => "red",
-                    Some('5') => "purple",
-                    _ => "normal",
-                }),
-        );
-        Ok(ConnectionFlag::Continue)
-    }
+        peer: &SocketAddr,
+        stream: &mut BufReader<S>,
+    ) -> Result<(), E>;
 }
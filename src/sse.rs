@@ -0,0 +1,236 @@
+use std::io::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use smol_str::SmolStr;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, BufReader, ReadHalf, WriteHalf};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{self, Instant};
+
+use crate::compress::{BodyWriter, Levels};
+use crate::http::{Request, Response};
+use crate::service::Service;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Splits on every line ending an event stream recognises, `\r\n`, `\r` and `\n`, keeping a trailing empty line.
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split("\r\n").flat_map(|line| line.split(['\r', '\n']))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    pub event: Option<SmolStr>,
+    pub id: Option<SmolStr>,
+    pub retry: Option<Duration>,
+    pub data: String,
+}
+
+impl Event {
+    #[inline]
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_event(mut self, event: impl Into<SmolStr>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    #[inline]
+    pub fn with_id(mut self, id: impl Into<SmolStr>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    #[inline]
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Frames the event; line breaks in `event` and `id` are dropped, and every line of `data` gets its own field, so
+    /// `data` cannot smuggle in other fields.
+    #[inline]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut fields = Vec::new();
+        if let Some(event) = self.event.as_ref() {
+            fields.push(format!("event: {}\n", event.replace(['\r', '\n'], "")));
+        }
+        if let Some(id) = self.id.as_ref() {
+            fields.push(format!("id: {}\n", id.replace(['\r', '\n', '\0'], "")));
+        }
+        if let Some(retry) = self.retry {
+            fields.push(format!("retry: {}\n", retry.as_millis()));
+        }
+        fields.extend(lines(&self.data).map(|line| format!("data: {line}\n")));
+        fields.push("\n".to_owned());
+        fields.concat().into_bytes()
+    }
+}
+
+/// The response head of an event stream; compression, if any, is negotiated on it by `CompressMiddleware`.
+#[inline]
+pub fn response() -> Response {
+    Response::ok()
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache")
+}
+
+/// The id of the last event the client received before reconnecting, if any.
+#[inline]
+pub fn last_event_id(request: &Request) -> Option<&str> {
+    request.headers.get("Last-Event-ID").map(SmolStr::as_str)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Closed {
+    /// The event source ran dry.
+    Source,
+    /// The client went away.
+    Client,
+}
+
+pub struct EventStream<S> {
+    reader: ReadHalf<S>,
+    writer: BodyWriter<WriteHalf<S>>,
+    heartbeat: Duration,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> EventStream<S> {
+    /// Compresses with `levels` if the response was given a `Content-Encoding`, which should be the levels of the
+    /// `CompressMiddleware` which negotiated it.
+    #[inline]
+    pub fn new(stream: S, response: &Response, levels: &Levels) -> Result<Self> {
+        let (reader, writer) = tokio::io::split(stream);
+        Ok(Self {
+            reader,
+            writer: BodyWriter::new(writer, response, levels)?,
+            heartbeat: Duration::from_secs(15),
+        })
+    }
+
+    #[inline]
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    #[inline]
+    pub async fn send(&mut self, event: &Event) -> Result<()> {
+        self.writer.send(&event.to_bytes()).await
+    }
+
+    /// Sends a comment line, which clients ignore but which keeps intermediaries from timing the stream out.
+    #[inline]
+    pub async fn comment(&mut self, text: &str) -> Result<()> {
+        let lines = if text.is_empty() {
+            ":\n".to_owned()
+        } else {
+            lines(text).map(|line| format!(": {line}\n")).collect()
+        };
+        self.writer.send(lines.as_bytes()).await
+    }
+
+    /// Forwards `events` to the client, sending a heartbeat comment whenever the stream has been quiet for
+    /// `heartbeat`, until either side goes away.
+    #[inline]
+    pub async fn forward(&mut self, mut events: Receiver<Event>) -> Result<Closed> {
+        let mut buf = [0_u8; 64];
+        let mut deadline = Instant::now() + self.heartbeat;
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(event) => self.send(&event).await?,
+                    None => return Ok(Closed::Source),
+                },
+                () = time::sleep_until(deadline) => self.comment("").await?,
+                read = self.reader.read(&mut buf) => match read {
+                    // clients do not send anything on an event stream, so this can only be a disconnect
+                    Ok(0) | Err(_) => return Ok(Closed::Client),
+                    Ok(_) => {}
+                },
+            }
+            deadline = Instant::now() + self.heartbeat;
+        }
+    }
+
+    /// Ends the stream, gracefully for the client when the source ran dry.
+    #[inline]
+    pub async fn finish(self) -> Result<()> {
+        self.writer.finish().await?;
+        Ok(())
+    }
+}
+
+/// Takes over the connection after a `text/event-stream` response, and forwards the events `source` returns for it.
+///
+/// `source` receives the request and its `Last-Event-ID`, so it can resume from there.
+pub struct EventStreamService<F> {
+    pub source: F,
+    pub heartbeat: Duration,
+    /// Levels to compress with, which should match those of the `CompressMiddleware`.
+    pub levels: Levels,
+}
+
+impl<F> EventStreamService<F> {
+    #[inline]
+    pub fn new(source: F) -> Self {
+        Self {
+            source,
+            heartbeat: Duration::from_secs(15),
+            levels: Levels::default(),
+        }
+    }
+
+    #[inline]
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    #[inline]
+    pub fn with_levels(mut self, levels: Levels) -> Self {
+        self.levels = levels;
+        self
+    }
+}
+
+#[async_trait]
+impl<E, S, F> Service<E, S> for EventStreamService<F>
+where
+    E: From<Error>,
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    F: Fn(&Request, Option<&str>) -> Option<Receiver<Event>> + Send + Sync,
+{
+    #[inline]
+    fn accepts(&self, _request: &Request, response: &Response) -> bool {
+        response
+            .headers
+            .get("Content-Type")
+            .is_some_and(|content_type| content_type.starts_with("text/event-stream"))
+    }
+
+    #[inline]
+    async fn call(
+        &self,
+        request: &Request,
+        response: &Response,
+        _peer: &SocketAddr,
+        stream: &mut BufReader<S>,
+    ) -> Result<(), E> {
+        let Some(events) = (self.source)(request, last_event_id(request)) else {
+            return Ok(());
+        };
+        let mut stream = EventStream::new(stream, response, &self.levels)?.with_heartbeat(self.heartbeat);
+        if stream.forward(events).await? == Closed::Source {
+            stream.finish().await?;
+        }
+        Ok(())
+    }
+}
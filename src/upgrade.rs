@@ -0,0 +1,109 @@
+use std::fmt::{Debug, Formatter};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::BoxError;
+
+pub type UpgradeFuture = Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>>;
+
+trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> Io for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+/// A connection handed over to another protocol, replaying the bytes the server read past the request first.
+pub struct Upgraded {
+    io: Box<dyn Io>,
+    buffered: Vec<u8>,
+    position: usize,
+}
+
+impl Debug for Upgraded {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Upgraded")
+            .field("buffered", &self.buffered())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Upgraded {
+    #[inline]
+    pub fn new<S>(io: S, buffered: Vec<u8>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        Self {
+            io: Box::new(io),
+            buffered,
+            position: 0,
+        }
+    }
+
+    /// The bytes that followed the request and have not been read yet.
+    #[inline]
+    pub fn buffered(&self) -> &[u8] {
+        self.buffered.get(self.position..).unwrap_or_default()
+    }
+}
+
+impl AsyncRead for Upgraded {
+    #[inline]
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let buffered = self.buffered();
+        if buffered.is_empty() {
+            return Pin::new(&mut self.io).poll_read(cx, buf);
+        }
+        let len = buffered.len().min(buf.remaining());
+        buf.put_slice(buffered.get(..len).unwrap_or_default());
+        self.position = self.position.saturating_add(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Upgraded {
+    #[inline]
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+/// Takes over the connection once the response carrying it has been written, for WebSocket, h2c, `CONNECT`
+/// tunnels or any other protocol.
+#[derive(Clone)]
+pub struct OnUpgrade(Arc<dyn Fn(Upgraded) -> UpgradeFuture + Send + Sync>);
+
+impl Debug for OnUpgrade {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("OnUpgrade").finish_non_exhaustive()
+    }
+}
+
+impl OnUpgrade {
+    #[inline]
+    pub fn new<F, Fut>(handler: F) -> Self
+    where
+        F: Fn(Upgraded) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), BoxError>> + Send + 'static,
+    {
+        Self(Arc::new(move |upgraded| Box::pin(handler(upgraded))))
+    }
+
+    #[inline]
+    pub async fn call(&self, upgraded: Upgraded) -> Result<(), BoxError> {
+        (self.0)(upgraded).await
+    }
+}
@@ -0,0 +1,165 @@
+use base64::prelude::*;
+use smol_str::{SmolStr, ToSmolStr};
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+
+use super::{DeflateParams, Error, Result, Role, WebSocketState, accept_key, has_token};
+use crate::http::{HeaderMap, Request, Response};
+
+/// The longest response head accepted from the server.
+const MAX_HEAD_LEN: usize = 16 * 1024;
+
+fn failed(reason: &str) -> Error {
+    Error::Handshake(reason.to_smolstr())
+}
+
+/// Generates a random `Sec-WebSocket-Key`.
+#[inline]
+pub fn generate_key() -> Result<String> {
+    let mut nonce = [0_u8; 16];
+    getrandom::fill(&mut nonce).map_err(std::io::Error::from)?;
+    Ok(BASE64_STANDARD.encode(nonce))
+}
+
+/// Reads the response head byte by byte, so the frames the server may send right after it stay in the stream.
+async fn read_head(stream: &mut (impl AsyncRead + Unpin)) -> Result<String> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_LEN {
+            return Err(failed("response head too long"));
+        }
+        head.push(stream.read_u8().await?);
+    }
+    String::from_utf8(head).map_err(|_invalid| failed("response head is not UTF-8"))
+}
+
+fn parse_response(head: &str) -> Result<Response> {
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, " ");
+    let (Some(version), Some(status_code)) = (parts.next(), parts.next()) else {
+        return Err(failed("bad status line"));
+    };
+    let mut response = Response::new(status_code, parts.next().unwrap_or_default());
+    response.version = version.into();
+    for (key, value) in lines.filter_map(|line| line.split_once(":")) {
+        response.headers.insert(key.trim(), value.trim());
+    }
+    Ok(response)
+}
+
+/// The opening handshake of a client connection.
+#[derive(Debug, Clone)]
+pub struct ClientHandshake {
+    pub host: SmolStr,
+    pub target: SmolStr,
+    /// Subprotocols to offer, in order of preference.
+    pub protocols: Vec<SmolStr>,
+    /// Offer `permessage-deflate`.
+    pub deflate: bool,
+    /// Extra headers sent with the request, such as `Origin` or `Authorization`.
+    pub headers: HeaderMap,
+}
+
+impl ClientHandshake {
+    #[inline]
+    pub fn new(host: impl Into<SmolStr>, target: impl Into<SmolStr>) -> Self {
+        Self {
+            host: host.into(),
+            target: target.into(),
+            protocols: Vec::new(),
+            deflate: false,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    #[inline]
+    pub fn with_protocols(mut self, protocols: impl IntoIterator<Item = impl Into<SmolStr>>) -> Self {
+        self.protocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    #[inline]
+    pub fn with_deflate(mut self, deflate: bool) -> Self {
+        self.deflate = deflate;
+        self
+    }
+
+    #[inline]
+    pub fn with_header(mut self, key: impl ToSmolStr, value: impl ToSmolStr) -> Self {
+        self.headers.insert(key, value);
+        self
+    }
+
+    /// Builds the upgrade request carrying `key`.
+    #[inline]
+    pub fn request(&self, key: &str) -> Request {
+        let mut headers = self.headers.clone();
+        headers.insert("Host", &self.host);
+        headers.insert("Upgrade", "websocket");
+        headers.insert("Connection", "Upgrade");
+        headers.insert("Sec-WebSocket-Key", key);
+        headers.insert("Sec-WebSocket-Version", "13");
+        if !self.protocols.is_empty() {
+            headers.insert("Sec-WebSocket-Protocol", self.protocols.join(", "));
+        }
+        if self.deflate {
+            // without `client_max_window_bits`, since the compressor always uses a 32K window
+            headers.insert("Sec-WebSocket-Extensions", "permessage-deflate");
+        }
+        Request {
+            method: "GET".into(),
+            target: self.target.clone(),
+            version: "HTTP/1.1".into(),
+            headers,
+            body: Vec::new(),
+        }
+    }
+
+    /// Checks that `response` accepts the upgrade request carrying `key`, and only agrees to what was offered.
+    #[inline]
+    pub fn verify(&self, key: &str, response: &Response) -> Result<()> {
+        if response.status_code != "101" {
+            return Err(Error::Handshake(
+                format!("unexpected status {}", response.response_line()).into(),
+            ));
+        }
+        if !has_token(response.headers.get("Upgrade"), "websocket")
+            || !has_token(response.headers.get("Connection"), "upgrade")
+        {
+            return Err(failed("missing upgrade headers"));
+        }
+        if response
+            .headers
+            .get("Sec-WebSocket-Accept")
+            .is_none_or(|accept| *accept != accept_key(key))
+        {
+            return Err(failed("wrong Sec-WebSocket-Accept"));
+        }
+        if let Some(protocol) = response.headers.get("Sec-WebSocket-Protocol")
+            && !self.protocols.contains(protocol)
+        {
+            return Err(failed("subprotocol was not offered"));
+        }
+        if let Some(extensions) = response.headers.get("Sec-WebSocket-Extensions") {
+            let single = !extensions.contains(",");
+            let params = DeflateParams::parse_offers(extensions).next();
+            if !(self.deflate && single && params.is_some_and(|params| params.client_max_window_bits.is_none())) {
+                return Err(failed("extension was not offered"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Performs the opening handshake over `stream`, returning the state of the client session.
+    #[inline]
+    pub async fn perform(&self, stream: &mut (impl AsyncRead + AsyncWrite + Unpin)) -> Result<WebSocketState> {
+        let key = generate_key()?;
+        stream.write_all(&self.request(&key).to_bytes()).await?;
+        stream.flush().await?;
+        let response = parse_response(&read_head(stream).await?)?;
+        self.verify(&key, &response)?;
+        Ok(WebSocketState::default()
+            .with_role(Role::Client)
+            .with_handshake(&response))
+    }
+}
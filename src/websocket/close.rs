@@ -0,0 +1,149 @@
+use std::fmt::{Display, Formatter};
+
+use super::{Error, Result};
+
+/// Status codes of a Close frame, as registered in RFC 6455 section 7.4.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CloseCode {
+    Normal,
+    GoingAway,
+    ProtocolError,
+    UnsupportedData,
+    /// 1005, never sent on the wire: the peer closed without a status code.
+    NoStatus,
+    /// 1006, never sent on the wire: the connection dropped without a Close frame.
+    Abnormal,
+    InvalidPayload,
+    PolicyViolation,
+    MessageTooBig,
+    MandatoryExtension,
+    InternalError,
+    ServiceRestart,
+    TryAgainLater,
+    BadGateway,
+    /// 1015, never sent on the wire: the TLS handshake failed.
+    TlsHandshake,
+    Other(u16),
+}
+
+impl From<u16> for CloseCode {
+    #[inline]
+    fn from(code: u16) -> Self {
+        match code {
+            1000 => Self::Normal,
+            1001 => Self::GoingAway,
+            1002 => Self::ProtocolError,
+            1003 => Self::UnsupportedData,
+            1005 => Self::NoStatus,
+            1006 => Self::Abnormal,
+            1007 => Self::InvalidPayload,
+            1008 => Self::PolicyViolation,
+            1009 => Self::MessageTooBig,
+            1010 => Self::MandatoryExtension,
+            1011 => Self::InternalError,
+            1012 => Self::ServiceRestart,
+            1013 => Self::TryAgainLater,
+            1014 => Self::BadGateway,
+            1015 => Self::TlsHandshake,
+            _ => Self::Other(code),
+        }
+    }
+}
+
+impl From<CloseCode> for u16 {
+    #[inline]
+    fn from(code: CloseCode) -> Self {
+        match code {
+            CloseCode::Normal => 1000,
+            CloseCode::GoingAway => 1001,
+            CloseCode::ProtocolError => 1002,
+            CloseCode::UnsupportedData => 1003,
+            CloseCode::NoStatus => 1005,
+            CloseCode::Abnormal => 1006,
+            CloseCode::InvalidPayload => 1007,
+            CloseCode::PolicyViolation => 1008,
+            CloseCode::MessageTooBig => 1009,
+            CloseCode::MandatoryExtension => 1010,
+            CloseCode::InternalError => 1011,
+            CloseCode::ServiceRestart => 1012,
+            CloseCode::TryAgainLater => 1013,
+            CloseCode::BadGateway => 1014,
+            CloseCode::TlsHandshake => 1015,
+            CloseCode::Other(code) => code,
+        }
+    }
+}
+
+impl CloseCode {
+    /// Whether the code may appear in a Close frame: registered codes other than 1004-1006 and 1015, and the
+    /// 3000-4999 range left to libraries and applications.
+    #[inline]
+    pub fn is_valid(self) -> bool {
+        matches!(u16::from(self), 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
+impl Display for CloseCode {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", u16::from(*self))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CloseFrame {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    /// A control frame payload is at most 125 bytes, 2 of which hold the code.
+    pub const MAX_REASON_LEN: usize = 123;
+
+    #[inline]
+    pub fn new(code: CloseCode, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+
+    #[inline]
+    pub fn normal() -> Self {
+        Self::new(CloseCode::Normal, "")
+    }
+
+    /// Encodes the frame payload; the reason is cut on a character boundary to fit, and `NoStatus` yields an empty
+    /// payload.
+    #[inline]
+    pub fn to_payload(&self) -> Vec<u8> {
+        if self.code == CloseCode::NoStatus {
+            return Vec::new();
+        }
+        let mut end = self.reason.len().min(Self::MAX_REASON_LEN);
+        while !self.reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut payload = u16::from(self.code).to_be_bytes().to_vec();
+        payload.extend_from_slice(self.reason.as_bytes().get(..end).unwrap_or_default());
+        payload
+    }
+
+    /// Decodes and validates a received payload; an empty one means the peer sent no status code.
+    #[inline]
+    pub fn from_payload(payload: &[u8]) -> Result<Self> {
+        let Some((&code, reason)) = payload.split_first_chunk::<2>() else {
+            return if payload.is_empty() {
+                Ok(Self::new(CloseCode::NoStatus, ""))
+            } else {
+                Err(Error::BadProtocol)
+            };
+        };
+        let code = CloseCode::from(u16::from_be_bytes(code));
+        if !code.is_valid() {
+            return Err(Error::InvalidCloseCode(code.into()));
+        }
+        let reason = String::from_utf8(reason.to_vec()).map_err(|_invalid| Error::InvalidUtf8)?;
+        Ok(Self { code, reason })
+    }
+}
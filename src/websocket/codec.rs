@@ -0,0 +1,150 @@
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+#[cfg(feature = "json")]
+use smol_str::ToSmolStr as _;
+
+use super::{CloseFrame, Error, Message, Result, WebSocket, WebSocketExt, WebSocketState};
+
+/// Converts between application values of type `T` and WebSocket messages.
+pub trait Codec<T>: Send + Sync {
+    fn encode(&self, item: &T) -> Result<Message>;
+
+    /// Fails with [`Error::UnsupportedData`] for the wrong kind of message, or [`Error::InvalidData`] when the
+    /// payload does not hold a `T`.
+    fn decode(&self, message: Message) -> Result<T>;
+}
+
+/// One JSON value per text message, terminated by a newline so a transcript of the messages is JSON Lines.
+#[cfg(feature = "json")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonLines;
+
+#[cfg(feature = "json")]
+impl<T> Codec<T> for JsonLines
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    #[inline]
+    fn encode(&self, item: &T) -> Result<Message> {
+        let mut text = serde_json::to_string(item).map_err(|error| Error::Encode(error.to_smolstr()))?;
+        text.push('\n');
+        Ok(Message::Text(text))
+    }
+
+    #[inline]
+    fn decode(&self, message: Message) -> Result<T> {
+        let Message::Text(text) = message else {
+            return Err(Error::UnsupportedData);
+        };
+        let line = text.strip_suffix('\n').unwrap_or(&text);
+        serde_json::from_str(line).map_err(|error| Error::InvalidData(error.to_smolstr()))
+    }
+}
+
+/// Binary messages holding a 32-bit big-endian length followed by that many bytes, for protocols framed that way
+/// over plain TCP too.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LengthPrefixed;
+
+impl<T> Codec<T> for LengthPrefixed
+where
+    T: AsRef<[u8]> + TryFrom<Vec<u8>>,
+{
+    #[inline]
+    fn encode(&self, item: &T) -> Result<Message> {
+        let payload = item.as_ref();
+        let len = u32::try_from(payload.len()).map_err(|_overflow| Error::Encode("payload too long".into()))?;
+        Ok(Message::Binary([&len.to_be_bytes(), payload].concat()))
+    }
+
+    #[inline]
+    fn decode(&self, message: Message) -> Result<T> {
+        let Message::Binary(mut data) = message else {
+            return Err(Error::UnsupportedData);
+        };
+        let Some((prefix, payload)) = data.split_first_chunk::<4>() else {
+            return Err(Error::InvalidData("missing length prefix".into()));
+        };
+        if usize::try_from(u32::from_be_bytes(*prefix)).ok() != Some(payload.len()) {
+            return Err(Error::InvalidData("length prefix mismatch".into()));
+        }
+        data.drain(..4);
+        T::try_from(data).map_err(|_invalid| Error::InvalidData("malformed payload".into()))
+    }
+}
+
+/// A session exchanging values of one type through a [`Codec`].
+///
+/// Run it with [`TypedWebSocket::run_typed`] to receive every message decoded in `on_item`; one which cannot be
+/// decoded fails the connection with 1003 Unsupported Data or 1007 Invalid Payload.
+pub trait TypedWebSocket: WebSocketExt {
+    type Item;
+    type Codec: Codec<Self::Item>;
+
+    fn codec(&self) -> &Self::Codec;
+
+    #[inline]
+    async fn on_item(&mut self, item: Self::Item) -> Result<()> {
+        let _ = item;
+        Ok(())
+    }
+
+    #[inline]
+    async fn send_typed(&mut self, item: &Self::Item) -> Result<()> {
+        let message = self.codec().encode(item)?;
+        self.send_message(message).await
+    }
+
+    #[inline]
+    fn decode(&self, message: Message) -> Result<Self::Item> {
+        self.codec().decode(message)
+    }
+
+    /// Like [`WebSocketExt::run`], with messages decoded and handed to `on_item` instead of `on_message`.
+    #[inline]
+    async fn run_typed(&mut self) -> Result<()> {
+        Typed(self).run().await
+    }
+}
+
+/// Runs a [`TypedWebSocket`] through the regular loop, decoding every message on its way to `on_item`.
+struct Typed<'a, S: ?Sized>(&'a mut S);
+
+impl<S> WebSocket for Typed<'_, S>
+where
+    S: TypedWebSocket + ?Sized,
+{
+    type Stream = S::Stream;
+
+    #[inline]
+    async fn stream_mut(&self) -> impl DerefMut<Target = Self::Stream> {
+        self.0.stream_mut().await
+    }
+
+    #[inline]
+    async fn state(&self) -> impl Deref<Target = WebSocketState> {
+        self.0.state().await
+    }
+
+    #[inline]
+    async fn state_mut(&self) -> impl DerefMut<Target = WebSocketState> {
+        self.0.state_mut().await
+    }
+
+    #[inline]
+    async fn on_message(&mut self, message: Message) -> Result<()> {
+        let item = self.0.decode(message)?;
+        self.0.on_item(item).await
+    }
+
+    #[inline]
+    async fn on_close(&mut self, frame: CloseFrame) -> Result<()> {
+        self.0.on_close(frame).await
+    }
+
+    #[inline]
+    async fn on_pong(&mut self, delay: Duration) -> Result<()> {
+        self.0.on_pong(delay).await
+    }
+}
@@ -0,0 +1,225 @@
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use super::{Error, Result};
+
+/// The bytes an RFC 7692 sender strips from the end of every compressed message, and the receiver appends back.
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Parameters of a `permessage-deflate` offer or response.
+///
+/// A `client_max_window_bits` offered without a value is represented as `Some(15)`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct DeflateParams {
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+    pub server_max_window_bits: Option<u8>,
+    pub client_max_window_bits: Option<u8>,
+}
+
+impl DeflateParams {
+    fn parse_window_bits(value: Option<&str>, default: Option<u8>) -> Option<u8> {
+        match value {
+            Some(value) => value
+                .trim_matches('"')
+                .parse()
+                .ok()
+                .filter(|bits| (8..=15).contains(bits)),
+            None => default,
+        }
+    }
+
+    /// Parses one extension of a `Sec-WebSocket-Extensions` header, returning `None` if it is not a valid
+    /// `permessage-deflate` one.
+    fn parse(extension: &str) -> Option<Self> {
+        let mut params = extension.split(";").map(str::trim);
+        if params.next()? != "permessage-deflate" {
+            return None;
+        }
+        let mut parsed = Self::default();
+        for param in params {
+            let (name, value) = match param.split_once("=") {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (param, None),
+            };
+            // every parameter may appear at most once
+            match name {
+                "server_no_context_takeover" if value.is_none() && !parsed.server_no_context_takeover => {
+                    parsed.server_no_context_takeover = true;
+                }
+                "client_no_context_takeover" if value.is_none() && !parsed.client_no_context_takeover => {
+                    parsed.client_no_context_takeover = true;
+                }
+                "server_max_window_bits" if parsed.server_max_window_bits.is_none() => {
+                    parsed.server_max_window_bits = Some(Self::parse_window_bits(value, None)?);
+                }
+                "client_max_window_bits" if parsed.client_max_window_bits.is_none() => {
+                    parsed.client_max_window_bits = Some(Self::parse_window_bits(value, Some(15))?);
+                }
+                _ => return None,
+            }
+        }
+        Some(parsed)
+    }
+
+    /// Parses every `permessage-deflate` offer of a `Sec-WebSocket-Extensions` header, skipping invalid ones.
+    #[inline]
+    pub fn parse_offers(header: &str) -> impl Iterator<Item = Self> {
+        header.split(",").filter_map(Self::parse)
+    }
+
+    /// Formats the parameters as a `Sec-WebSocket-Extensions` value.
+    #[inline]
+    pub fn to_header(&self) -> String {
+        let mut header = String::from("permessage-deflate");
+        if self.server_no_context_takeover {
+            header.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            header.push_str("; client_no_context_takeover");
+        }
+        if let Some(bits) = self.server_max_window_bits {
+            header.push_str("; server_max_window_bits=");
+            header.push_str(&bits.to_string());
+        }
+        if let Some(bits) = self.client_max_window_bits {
+            header.push_str("; client_max_window_bits=");
+            header.push_str(&bits.to_string());
+        }
+        header
+    }
+}
+
+/// What the server is willing to agree to when a client offers `permessage-deflate`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeflateConfig {
+    /// Reset the compressor after every message, trading ratio for memory.
+    pub server_no_context_takeover: bool,
+    /// Ask the client to reset its compressor after every message.
+    pub client_no_context_takeover: bool,
+    /// Ask the client to use a smaller sliding window, if it announced support for it.
+    pub client_max_window_bits: Option<u8>,
+}
+
+impl DeflateConfig {
+    /// Picks the first offer of a `Sec-WebSocket-Extensions` header the server can honour, and returns the
+    /// parameters to answer with.
+    #[inline]
+    pub fn negotiate(&self, header: &str) -> Option<DeflateParams> {
+        DeflateParams::parse_offers(header).find_map(|offer| {
+            // flate2's default backend always compresses with a 32K window
+            if offer.server_max_window_bits.is_some_and(|bits| bits < 15) {
+                return None;
+            }
+            Some(DeflateParams {
+                server_no_context_takeover: offer.server_no_context_takeover || self.server_no_context_takeover,
+                client_no_context_takeover: offer.client_no_context_takeover || self.client_no_context_takeover,
+                server_max_window_bits: offer.server_max_window_bits,
+                client_max_window_bits: offer
+                    .client_max_window_bits
+                    .and_then(|offered| self.client_max_window_bits.map(|bits| bits.min(offered))),
+            })
+        })
+    }
+}
+
+/// The compression contexts of a connection which negotiated `permessage-deflate`.
+#[derive(Debug)]
+pub struct Deflate {
+    compress: Compress,
+    decompress: Decompress,
+    reset_compress: bool,
+    reset_decompress: bool,
+}
+
+impl Deflate {
+    /// The server side of a connection which agreed on `params`.
+    #[inline]
+    pub fn server(params: &DeflateParams, level: Compression) -> Self {
+        Self {
+            compress: Compress::new(level, false),
+            decompress: Decompress::new(false),
+            reset_compress: params.server_no_context_takeover,
+            reset_decompress: params.client_no_context_takeover,
+        }
+    }
+
+    /// The client side of a connection which agreed on `params`.
+    #[inline]
+    pub fn client(params: &DeflateParams, level: Compression) -> Self {
+        Self {
+            compress: Compress::new(level, false),
+            decompress: Decompress::new(false),
+            reset_compress: params.client_no_context_takeover,
+            reset_decompress: params.server_no_context_takeover,
+        }
+    }
+
+    /// Compresses a whole message payload, to be sent with RSV1 set on its first frame.
+    #[inline]
+    pub fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity((data.len() >> 1) + 64);
+        let mut input = data;
+        loop {
+            output.reserve(output.capacity().max(64));
+            let before = self.compress.total_in();
+            self.compress
+                .compress_vec(input, &mut output, FlushCompress::Sync)
+                .map_err(std::io::Error::other)?;
+            let consumed = usize::try_from(self.compress.total_in() - before).map_err(std::io::Error::other)?;
+            input = input.get(consumed..).unwrap_or_default();
+            // the sync flush is over once it leaves room in the output
+            if input.is_empty() && output.len() < output.capacity() {
+                break;
+            }
+        }
+        if output.ends_with(&TAIL) {
+            output.truncate(output.len() - TAIL.len());
+        }
+        if self.reset_compress {
+            self.compress.reset();
+        }
+        Ok(output)
+    }
+
+    /// Decompresses a whole message payload received with RSV1 set, failing with [`Error::MessageTooBig`] once it
+    /// inflates past `max_size` bytes.
+    #[inline]
+    pub fn decompress(&mut self, data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        let mut input = Vec::with_capacity(data.len() + TAIL.len());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&TAIL);
+        let mut rest = input.as_slice();
+        let mut output = Vec::with_capacity((data.len() * 2 + 64).min(max_size));
+        loop {
+            // grow geometrically, but never far beyond the limit
+            let remaining = max_size.saturating_sub(output.len()).saturating_add(1);
+            output.reserve(output.capacity().max(64).min(remaining));
+            let before = self.decompress.total_in();
+            let status = self
+                .decompress
+                .decompress_vec(rest, &mut output, FlushDecompress::Sync)
+                .map_err(|_corrupt| Error::InvalidCompressedData)?;
+            let consumed = usize::try_from(self.decompress.total_in() - before).map_err(std::io::Error::other)?;
+            rest = rest.get(consumed..).unwrap_or_default();
+            if output.len() > max_size {
+                self.decompress.reset(false);
+                return Err(Error::MessageTooBig);
+            }
+            let has_room = output.len() < output.capacity();
+            match status {
+                // a final block ends the stream, the next message starts a new one
+                Status::StreamEnd => {
+                    self.decompress.reset(false);
+                    break;
+                }
+                Status::Ok | Status::BufError if rest.is_empty() && has_room => break,
+                Status::BufError if consumed == 0 && has_room => return Err(Error::InvalidCompressedData),
+                Status::Ok | Status::BufError => {}
+            }
+        }
+        if self.reset_decompress {
+            self.decompress.reset(false);
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,344 @@
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+
+use super::{Error, Result};
+
+#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Opcode {
+    Continuation = 0,
+    Text = 1,
+    Binary = 2,
+    Close = 8,
+    Ping = 9,
+    Pong = 10,
+}
+
+impl Opcode {
+    #[inline]
+    pub fn is_control(self) -> bool {
+        self >= Self::Close
+    }
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = Error;
+
+    #[inline]
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Opcode::Continuation),
+            1 => Ok(Opcode::Text),
+            2 => Ok(Opcode::Binary),
+            8 => Ok(Opcode::Close),
+            9 => Ok(Opcode::Ping),
+            10 => Ok(Opcode::Pong),
+            _ => Err(Error::InvalidOpcode(value)),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    #[inline]
+    fn from(opcode: Opcode) -> Self {
+        #[expect(clippy::as_conversions, reason = "Opcode is repr(u8)")]
+        let code = opcode as u8;
+        code
+    }
+}
+
+/// Which end of the connection this side is: clients mask every frame they send, servers never do.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Role {
+    #[default]
+    Server,
+    Client,
+}
+
+impl Role {
+    /// A fresh masking key for the next frame sent in this role.
+    #[inline]
+    pub fn mask(self) -> Result<Option<[u8; 4]>> {
+        match self {
+            Self::Server => Ok(None),
+            Self::Client => {
+                let mut mask = [0_u8; 4];
+                getrandom::fill(&mut mask).map_err(std::io::Error::from)?;
+                Ok(Some(mask))
+            }
+        }
+    }
+
+    /// Whether frames received in this role must be masked.
+    #[inline]
+    pub fn expects_mask(self) -> bool {
+        self == Self::Server
+    }
+}
+
+/// XORs `data` with `mask`, as if `data` started at `offset` bytes into the payload.
+#[inline]
+pub fn apply_mask(mask: [u8; 4], data: &mut [u8], offset: usize) {
+    for (value, key) in data.iter_mut().zip(mask.iter().cycle().skip(offset % 4)) {
+        *value ^= key;
+    }
+}
+
+/// The fixed part of a frame, up to (and including) the masking key.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FrameHeader {
+    pub fin: bool,
+    pub rsv1: bool,
+    pub rsv2: bool,
+    pub rsv3: bool,
+    pub opcode: Opcode,
+    pub mask: Option<[u8; 4]>,
+    pub payload_len: u64,
+}
+
+impl FrameHeader {
+    /// The longest encoded header: 2 bytes, an 8 bytes extended length and a 4 bytes masking key.
+    pub const MAX_LEN: usize = 14;
+
+    #[inline]
+    pub fn new(fin: bool, opcode: Opcode, payload_len: u64) -> Self {
+        Self {
+            fin,
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+            opcode,
+            mask: None,
+            payload_len,
+        }
+    }
+
+    #[inline]
+    pub fn with_mask(mut self, mask: [u8; 4]) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    #[inline]
+    pub fn with_rsv1(mut self, rsv1: bool) -> Self {
+        self.rsv1 = rsv1;
+        self
+    }
+
+    /// Length of the encoded header.
+    #[inline]
+    pub fn encoded_len(&self) -> usize {
+        let extended = match self.payload_len {
+            0..=125 => 0,
+            126..=0xffff => 2,
+            _ => 8,
+        };
+        let mask = if self.mask.is_some() { 4 } else { 0 };
+        2 + extended + mask
+    }
+
+    /// Whether any of the reserved bits is set.
+    #[inline]
+    pub fn has_rsv(&self) -> bool {
+        self.rsv1 || self.rsv2 || self.rsv3
+    }
+
+    #[inline]
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let head0 = (u8::from(self.fin) << 7)
+            | (u8::from(self.rsv1) << 6)
+            | (u8::from(self.rsv2) << 5)
+            | (u8::from(self.rsv3) << 4)
+            | u8::from(self.opcode);
+        let masked = u8::from(self.mask.is_some()) << 7;
+        if let Ok(len @ ..=125) = u8::try_from(self.payload_len) {
+            buf.extend([head0, masked | len]);
+        } else if let Ok(len) = u16::try_from(self.payload_len) {
+            buf.extend([head0, masked | 126]);
+            buf.extend(len.to_be_bytes());
+        } else {
+            buf.extend([head0, masked | 127]);
+            buf.extend(self.payload_len.to_be_bytes());
+        }
+        if let Some(mask) = self.mask {
+            buf.extend(mask);
+        }
+    }
+
+    /// Parses the first two bytes, returning the header with the 7-bit length and whether a mask follows.
+    fn parse_head([head0, head1]: [u8; 2]) -> Result<(Self, bool)> {
+        let header = Self {
+            fin: head0 & 0x80 != 0,
+            rsv1: head0 & 0x40 != 0,
+            rsv2: head0 & 0x20 != 0,
+            rsv3: head0 & 0x10 != 0,
+            opcode: Opcode::try_from(head0 & 0x0f)?,
+            mask: None,
+            payload_len: u64::from(head1 & 0x7f),
+        };
+        Ok((header, head1 & 0x80 != 0))
+    }
+
+    fn check_len(payload_len: u64) -> Result<u64> {
+        // the most significant bit of a 64-bit length must be 0
+        if payload_len >> 63 != 0 {
+            return Err(Error::BadProtocol);
+        }
+        Ok(payload_len)
+    }
+
+    /// Decodes a header from the start of `buf`, returning it with its encoded length, or `None` if `buf` is too
+    /// short to hold it.
+    #[inline]
+    pub fn decode(buf: &[u8]) -> Result<Option<(Self, usize)>> {
+        let Some(&head) = buf.first_chunk::<2>() else {
+            return Ok(None);
+        };
+        let (mut header, masked) = Self::parse_head(head)?;
+        let rest = buf.get(2..).unwrap_or_default();
+        let (payload_len, rest) = match header.payload_len {
+            126 => match rest.split_first_chunk::<2>() {
+                Some((&len, rest)) => (u64::from(u16::from_be_bytes(len)), rest),
+                None => return Ok(None),
+            },
+            127 => match rest.split_first_chunk::<8>() {
+                Some((&len, rest)) => (Self::check_len(u64::from_be_bytes(len))?, rest),
+                None => return Ok(None),
+            },
+            len => (len, rest),
+        };
+        header.payload_len = payload_len;
+        if masked {
+            let Some(&mask) = rest.first_chunk::<4>() else {
+                return Ok(None);
+            };
+            header.mask = Some(mask);
+        }
+        Ok(Some((header, header.encoded_len())))
+    }
+
+    #[inline]
+    pub async fn read(stream: &mut (impl AsyncRead + Unpin)) -> Result<Self> {
+        let mut head = [0_u8; 2];
+        stream.read_exact(&mut head).await?;
+        let (mut header, masked) = Self::parse_head(head)?;
+        header.payload_len = match header.payload_len {
+            126 => u64::from(stream.read_u16().await?),
+            127 => Self::check_len(stream.read_u64().await?)?,
+            len => len,
+        };
+        if masked {
+            let mut mask = [0_u8; 4];
+            stream.read_exact(&mut mask).await?;
+            header.mask = Some(mask);
+        }
+        Ok(header)
+    }
+}
+
+/// A whole frame, whose payload is kept unmasked.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Frame {
+    pub header: FrameHeader,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// The longest payload [`Frame::read`] accepts, the default [`WebSocketState::max_frame_size`].
+    ///
+    /// [`WebSocketState::max_frame_size`]: super::WebSocketState::max_frame_size
+    pub const MAX_PAYLOAD_LEN: usize = 16 << 20;
+
+    #[inline]
+    pub fn new(fin: bool, opcode: Opcode, payload: Vec<u8>) -> Self {
+        #[expect(clippy::as_conversions, reason = "usize::MAX <= u64::MAX")]
+        let header = FrameHeader::new(fin, opcode, payload.len() as u64);
+        Self { header, payload }
+    }
+
+    #[inline]
+    pub fn with_mask(mut self, mask: [u8; 4]) -> Self {
+        self.header.mask = Some(mask);
+        self
+    }
+
+    /// Encodes the frame, masking the payload if the header carries a mask.
+    #[inline]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.header.encoded_len() + self.payload.len());
+        self.header.encode(&mut buf);
+        let start = buf.len();
+        buf.extend_from_slice(&self.payload);
+        if let Some(mask) = self.header.mask
+            && let Some(payload) = buf.get_mut(start..)
+        {
+            apply_mask(mask, payload, 0);
+        }
+        buf
+    }
+
+    /// Decodes a frame from the start of `buf`, returning it with its encoded length, or `None` if `buf` does not hold
+    /// a whole frame yet.
+    #[inline]
+    pub fn decode(buf: &[u8]) -> Result<Option<(Self, usize)>> {
+        let Some((header, header_len)) = FrameHeader::decode(buf)? else {
+            return Ok(None);
+        };
+        let payload_len = usize::try_from(header.payload_len).map_err(|_overflow| Error::BadProtocol)?;
+        let Some(payload) = buf.get(header_len..).and_then(|rest| rest.get(..payload_len)) else {
+            return Ok(None);
+        };
+        let mut payload = payload.to_vec();
+        if let Some(mask) = header.mask {
+            apply_mask(mask, &mut payload, 0);
+        }
+        Ok(Some((Self { header, payload }, header_len + payload_len)))
+    }
+
+    /// Reads the payload following `header` and unmasks it, refusing one longer than [`Frame::MAX_PAYLOAD_LEN`].
+    #[inline]
+    pub async fn read_payload(header: FrameHeader, stream: &mut (impl AsyncRead + Unpin)) -> Result<Self> {
+        Self::read_payload_with_limit(header, stream, Self::MAX_PAYLOAD_LEN).await
+    }
+
+    /// Reads the payload following `header` and unmasks it, failing with [`Error::MessageTooBig`] before reading any
+    /// of it if it is longer than `max_len`.
+    #[inline]
+    pub async fn read_payload_with_limit(
+        header: FrameHeader,
+        stream: &mut (impl AsyncRead + Unpin),
+        max_len: usize,
+    ) -> Result<Self> {
+        let payload_len = usize::try_from(header.payload_len).map_err(|_overflow| Error::MessageTooBig)?;
+        if payload_len > max_len {
+            return Err(Error::MessageTooBig);
+        }
+        // grows with what actually arrives instead of trusting the declared length
+        let mut payload = Vec::new();
+        if stream.take(header.payload_len).read_to_end(&mut payload).await? < payload_len {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        if let Some(mask) = header.mask {
+            apply_mask(mask, &mut payload, 0);
+        }
+        Ok(Self { header, payload })
+    }
+
+    #[inline]
+    pub async fn read(stream: &mut (impl AsyncRead + Unpin)) -> Result<Self> {
+        Self::read_with_limit(stream, Self::MAX_PAYLOAD_LEN).await
+    }
+
+    /// Reads a frame, failing with [`Error::MessageTooBig`] before reading its payload if it is longer than `max_len`.
+    #[inline]
+    pub async fn read_with_limit(stream: &mut (impl AsyncRead + Unpin), max_len: usize) -> Result<Self> {
+        let header = FrameHeader::read(stream).await?;
+        Self::read_payload_with_limit(header, stream, max_len).await
+    }
+
+    #[inline]
+    pub async fn write(&self, stream: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+        stream.write_all(&self.encode()).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use base64::prelude::*;
+use sha1_smol::Sha1;
+use smol_str::SmolStr;
+
+use super::{DeflateConfig, WebSocketState, has_token};
+use crate::BoxError;
+use crate::http::{Request, Response};
+use crate::middleware::{Middleware, Next};
+use crate::upgrade::{UpgradeFuture, Upgraded};
+
+/// Picks one of the subprotocols offered by the client, or `None` to proceed without one.
+pub type ProtocolSelector = dyn Fn(&Request, &[&str]) -> Option<SmolStr> + Send + Sync;
+
+/// Runs a session on an upgraded connection, given the state negotiated by the handshake.
+pub type SessionHandler = dyn Fn(Upgraded, WebSocketState) -> UpgradeFuture + Send + Sync;
+
+/// Computes the `Sec-WebSocket-Accept` value answering a `Sec-WebSocket-Key`.
+#[inline]
+pub fn accept_key(key: &str) -> String {
+    let concated = [key, "258EAFA5-E914-47DA-95CA-C5AB0DC85B11"].concat();
+    let hashed = Sha1::from(concated).digest().bytes();
+    BASE64_STANDARD.encode(hashed)
+}
+
+#[derive(Default)]
+pub struct WebSocketHandshakeMiddleware {
+    /// Accept `permessage-deflate` offers on these terms; `None` declines compression.
+    pub deflate: Option<DeflateConfig>,
+    /// Supported subprotocols; the first one the client offers is selected.
+    pub protocols: Vec<SmolStr>,
+    /// Overrides the selection among `protocols`.
+    pub protocol_selector: Option<Box<ProtocolSelector>>,
+    /// Origins allowed to open a connection; `None` allows any.
+    ///
+    /// Requests without an `Origin` header do not come from a browser and are always allowed.
+    pub allowed_origins: Option<Vec<SmolStr>>,
+    /// Takes over accepted connections; without it, a service has to look for the 101 response instead.
+    pub handler: Option<Arc<SessionHandler>>,
+}
+
+impl Debug for WebSocketHandshakeMiddleware {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocketHandshakeMiddleware")
+            .field("deflate", &self.deflate)
+            .field("protocols", &self.protocols)
+            .field("allowed_origins", &self.allowed_origins)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WebSocketHandshakeMiddleware {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn with_deflate(mut self, deflate: DeflateConfig) -> Self {
+        self.deflate = Some(deflate);
+        self
+    }
+
+    #[inline]
+    pub fn with_protocols(mut self, protocols: impl IntoIterator<Item = impl Into<SmolStr>>) -> Self {
+        self.protocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    #[inline]
+    pub fn with_protocol_selector(
+        mut self,
+        selector: impl Fn(&Request, &[&str]) -> Option<SmolStr> + Send + Sync + 'static,
+    ) -> Self {
+        self.protocol_selector = Some(Box::new(selector));
+        self
+    }
+
+    #[inline]
+    pub fn with_allowed_origins(mut self, origins: impl IntoIterator<Item = impl Into<SmolStr>>) -> Self {
+        self.allowed_origins = Some(origins.into_iter().map(Into::into).collect());
+        self
+    }
+
+    #[inline]
+    pub fn with_handler<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Upgraded, WebSocketState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), BoxError>> + Send + 'static,
+    {
+        self.handler = Some(Arc::new(move |upgraded, state| Box::pin(handler(upgraded, state))));
+        self
+    }
+
+    fn is_origin_allowed(&self, request: &Request) -> bool {
+        match (self.allowed_origins.as_ref(), request.headers.get("Origin")) {
+            (Some(allowed), Some(origin)) => allowed.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)),
+            (Some(_) | None, None) | (None, Some(_)) => true,
+        }
+    }
+
+    fn select_protocol(&self, request: &Request) -> Option<SmolStr> {
+        let offered = request
+            .headers
+            .get("Sec-WebSocket-Protocol")?
+            .split(",")
+            .map(str::trim)
+            .filter(|protocol| !protocol.is_empty())
+            .collect::<Vec<_>>();
+        match self.protocol_selector.as_ref() {
+            Some(selector) => selector(request, &offered),
+            None => offered
+                .into_iter()
+                .find(|&protocol| self.protocols.iter().any(|supported| supported == protocol))
+                .map(SmolStr::from),
+        }
+    }
+}
+
+#[async_trait]
+impl<E> Middleware<E> for WebSocketHandshakeMiddleware {
+    #[inline]
+    async fn call(&self, request: &Request, next: &dyn Next<E>) -> Result<Response, E> {
+        if !has_token(request.headers.get("Upgrade"), "websocket") {
+            return next.call(request).await;
+        }
+        if request.method != "GET" || !has_token(request.headers.get("Connection"), "upgrade") {
+            return Ok(Response::new(400, "Bad Request"));
+        }
+        if request
+            .headers
+            .get("Sec-WebSocket-Version")
+            .is_none_or(|version| version != "13")
+        {
+            return Ok(Response::new(426, "Upgrade Required").with_header("Sec-WebSocket-Version", "13"));
+        }
+        if !self.is_origin_allowed(request) {
+            return Ok(Response::new(403, "Forbidden"));
+        }
+        let Some(key) = request
+            .headers
+            .get("Sec-WebSocket-Key")
+            .filter(|key| BASE64_STANDARD.decode(key).is_ok_and(|nonce| nonce.len() == 16))
+        else {
+            return Ok(Response::new(400, "Bad Request"));
+        };
+
+        let mut response = Response::new(101, "Switching Protocols")
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", accept_key(key))
+            .with_header("Sec-WebSocket-Version", "13");
+        if let Some(protocol) = self.select_protocol(request) {
+            response = response.with_header("Sec-WebSocket-Protocol", protocol);
+        }
+        if let Some(config) = self.deflate.as_ref()
+            && let Some(offers) = request.headers.get("Sec-WebSocket-Extensions")
+            && let Some(params) = config.negotiate(offers)
+        {
+            response = response.with_header("Sec-WebSocket-Extensions", params.to_header());
+        }
+        if let Some(handler) = self.handler.as_ref() {
+            let handler = Arc::clone(handler);
+            let handshake = response.clone();
+            response = response
+                .with_upgrade(move |upgraded| handler(upgraded, WebSocketState::default().with_handshake(&handshake)));
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use smol_str::SmolStr;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use super::{CloseCode, CloseFrame, Message, Result, WebSocketSender};
+
+pub type SessionId = u64;
+
+/// What to do with a session whose outbound queue is full when a message is broadcast.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum SlowConsumer {
+    /// Skip the message for that session only.
+    #[default]
+    Drop,
+    /// Remove the session from the hub, which closes its connection with 1008 Policy Violation.
+    Disconnect,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    next_id: SessionId,
+    sessions: BTreeMap<SessionId, mpsc::Sender<Message>>,
+    rooms: BTreeMap<SmolStr, BTreeSet<SessionId>>,
+}
+
+impl Registry {
+    fn remove(&mut self, id: SessionId) {
+        self.sessions.remove(&id);
+        self.rooms.retain(|_, members| {
+            members.remove(&id);
+            !members.is_empty()
+        });
+    }
+}
+
+/// A registry of connected sessions, grouped into rooms (or topics) that messages are broadcast to.
+///
+/// Cloning a hub yields another handle to the same registry.
+#[derive(Debug, Clone)]
+pub struct Hub {
+    registry: Arc<Mutex<Registry>>,
+    queue_size: usize,
+    slow_consumer: SlowConsumer,
+}
+
+impl Default for Hub {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            registry: Arc::default(),
+            queue_size: 64,
+            slow_consumer: SlowConsumer::default(),
+        }
+    }
+}
+
+impl Hub {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Messages each session may have pending before the slow-consumer policy applies.
+    #[inline]
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size.max(1);
+        self
+    }
+
+    #[inline]
+    pub fn with_slow_consumer(mut self, slow_consumer: SlowConsumer) -> Self {
+        self.slow_consumer = slow_consumer;
+        self
+    }
+
+    fn registry(&self) -> MutexGuard<'_, Registry> {
+        // the registry is consistent after every statement, so a panic elsewhere cannot corrupt it
+        self.registry.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds a session, which leaves every room when the returned subscription is dropped.
+    #[inline]
+    pub fn register(&self) -> Subscription {
+        let (sender, messages) = mpsc::channel(self.queue_size);
+        let mut registry = self.registry();
+        let id = registry.next_id;
+        registry.next_id = id.wrapping_add(1);
+        registry.sessions.insert(id, sender);
+        Subscription {
+            id,
+            hub: self.clone(),
+            messages,
+        }
+    }
+
+    #[inline]
+    pub fn join(&self, id: SessionId, room: impl Into<SmolStr>) {
+        let mut registry = self.registry();
+        if registry.sessions.contains_key(&id) {
+            registry.rooms.entry(room.into()).or_default().insert(id);
+        }
+    }
+
+    #[inline]
+    pub fn leave(&self, id: SessionId, room: &str) {
+        let mut registry = self.registry();
+        if let Some(members) = registry.rooms.get_mut(room) {
+            members.remove(&id);
+            if members.is_empty() {
+                registry.rooms.remove(room);
+            }
+        }
+    }
+
+    /// The sessions currently in `room`.
+    #[inline]
+    pub fn members(&self, room: &str) -> Vec<SessionId> {
+        self.registry()
+            .rooms
+            .get(room)
+            .map(|members| members.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Queues `message` for every session in `room`, returning how many sessions it was queued for.
+    #[inline]
+    pub fn broadcast(&self, room: &str, message: impl Into<Message>) -> usize {
+        let message = message.into();
+        let mut registry = self.registry();
+        let mut queued = 0_usize;
+        let mut slow = Vec::new();
+        for id in registry.rooms.get(room).into_iter().flatten() {
+            let Some(sender) = registry.sessions.get(id) else {
+                continue;
+            };
+            match sender.try_send(message.clone()) {
+                Ok(()) => queued = queued.saturating_add(1),
+                Err(TrySendError::Full(_)) if self.slow_consumer == SlowConsumer::Disconnect => slow.push(*id),
+                Err(TrySendError::Full(_) | TrySendError::Closed(_)) => {}
+            }
+        }
+        for id in slow {
+            registry.remove(id);
+        }
+        queued
+    }
+}
+
+/// A session's membership of a [`Hub`], receiving the messages broadcast to its rooms.
+#[derive(Debug)]
+pub struct Subscription {
+    id: SessionId,
+    hub: Hub,
+    messages: mpsc::Receiver<Message>,
+}
+
+impl Subscription {
+    #[inline]
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+
+    #[inline]
+    pub fn join(&self, room: impl Into<SmolStr>) {
+        self.hub.join(self.id, room);
+    }
+
+    #[inline]
+    pub fn leave(&self, room: &str) {
+        self.hub.leave(self.id, room);
+    }
+
+    /// The next broadcast message, or `None` once the hub disconnected this session as a slow consumer.
+    #[inline]
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.messages.recv().await
+    }
+
+    /// Writes broadcast messages to `sender` until the connection goes away or the hub disconnects the session.
+    #[inline]
+    pub async fn forward(&mut self, sender: &WebSocketSender) -> Result<()> {
+        while let Some(message) = self.recv().await {
+            sender.send(message).await?;
+        }
+        sender
+            .close(CloseFrame::new(CloseCode::PolicyViolation, "too slow"))
+            .await
+    }
+}
+
+impl Drop for Subscription {
+    #[inline]
+    fn drop(&mut self) {
+        self.hub.registry().remove(self.id);
+    }
+}
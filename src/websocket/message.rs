@@ -0,0 +1,112 @@
+use super::{Error, Opcode, Result};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Message {
+    #[inline]
+    pub fn opcode(&self) -> Opcode {
+        match *self {
+            Self::Text(_) => Opcode::Text,
+            Self::Binary(_) => Opcode::Binary,
+        }
+    }
+
+    #[inline]
+    #[expect(clippy::pattern_type_mismatch, reason = "binding the payload by reference")]
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Text(text) => text.as_bytes(),
+            Self::Binary(data) => data,
+        }
+    }
+
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Text(text) => text.into_bytes(),
+            Self::Binary(data) => data,
+        }
+    }
+
+    /// Builds a message from an assembled data frame payload, validating text as UTF-8.
+    #[inline]
+    pub fn from_parts(opcode: Opcode, data: Vec<u8>) -> Result<Self> {
+        match opcode {
+            Opcode::Text => String::from_utf8(data)
+                .map(Self::Text)
+                .map_err(|_invalid| Error::InvalidUtf8),
+            Opcode::Binary => Ok(Self::Binary(data)),
+            Opcode::Continuation | Opcode::Close | Opcode::Ping | Opcode::Pong => Err(Error::BadProtocol),
+        }
+    }
+}
+
+impl From<String> for Message {
+    #[inline]
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    #[inline]
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_owned())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    #[inline]
+    fn from(data: Vec<u8>) -> Self {
+        Self::Binary(data)
+    }
+}
+
+/// Validates UTF-8 text received in arbitrary chunks, failing as soon as an invalid sequence shows up, even when it
+/// spans chunk boundaries.
+#[derive(Debug, Default, Clone)]
+pub struct Utf8Validator {
+    /// The start of a character whose remaining bytes have not been received yet.
+    pending: Vec<u8>,
+}
+
+impl Utf8Validator {
+    #[inline]
+    pub fn feed(&mut self, mut chunk: &[u8]) -> Result<()> {
+        while !self.pending.is_empty() {
+            let Some((&byte, rest)) = chunk.split_first() else {
+                return Ok(());
+            };
+            self.pending.push(byte);
+            chunk = rest;
+            match str::from_utf8(&self.pending) {
+                Ok(_) => self.pending.clear(),
+                Err(error) if error.error_len().is_some() => return Err(Error::InvalidUtf8),
+                Err(_) => {}
+            }
+        }
+        match str::from_utf8(chunk) {
+            Ok(_) => Ok(()),
+            Err(error) if error.error_len().is_some() => Err(Error::InvalidUtf8),
+            Err(error) => {
+                self.pending = chunk.get(error.valid_up_to()..).unwrap_or_default().to_vec();
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks that the text does not end in the middle of a character, and resets the validator.
+    #[inline]
+    pub fn finish(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            Ok(())
+        } else {
+            self.pending.clear();
+            Err(Error::InvalidUtf8)
+        }
+    }
+}
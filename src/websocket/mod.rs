@@ -0,0 +1,769 @@
+#![allow(
+    async_fn_in_trait,
+    reason = "Yes, I don't care about auto traits like `Send` on the `Future`"
+)]
+
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+use flate2::Compression;
+use smol_str::SmolStr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{self, Instant};
+
+use crate::http::Response;
+
+pub mod client;
+mod close;
+mod codec;
+mod deflate;
+mod frame;
+mod handshake;
+mod hub;
+mod message;
+mod split;
+
+pub use close::{CloseCode, CloseFrame};
+#[cfg(feature = "json")]
+pub use codec::JsonLines;
+pub use codec::{Codec, LengthPrefixed, TypedWebSocket};
+pub use deflate::{Deflate, DeflateConfig, DeflateParams};
+pub use frame::{Frame, FrameHeader, Opcode, Role, apply_mask};
+pub use handshake::{ProtocolSelector, SessionHandler, WebSocketHandshakeMiddleware, accept_key};
+pub use hub::{Hub, SessionId, SlowConsumer, Subscription};
+pub use message::{Message, Utf8Validator};
+pub use split::{WebSocketReceiver, WebSocketSender, split};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("IO Error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("Invalid Opcode: {0}")]
+    InvalidOpcode(u8),
+    #[error("Bad Protocol")]
+    BadProtocol,
+    #[error("Invalid Close Code: {0}")]
+    InvalidCloseCode(u16),
+    #[error("Invalid UTF-8")]
+    InvalidUtf8,
+    #[error("Invalid Compressed Data")]
+    InvalidCompressedData,
+    #[error("Message Too Big")]
+    MessageTooBig,
+    #[error("Unsupported Data")]
+    UnsupportedData,
+    #[error("Invalid Data: {0}")]
+    InvalidData(SmolStr),
+    #[error("Encode Failed: {0}")]
+    Encode(SmolStr),
+    #[error("Pong Timeout")]
+    PongTimeout,
+    #[error("Idle Timeout")]
+    IdleTimeout,
+    #[error("Handshake Failed: {0}")]
+    Handshake(SmolStr),
+    #[error("Connection Closed")]
+    Closed,
+}
+
+impl Error {
+    /// The status code to close the connection with when this error is caused by the peer.
+    #[inline]
+    pub fn close_code(&self) -> Option<CloseCode> {
+        match *self {
+            Self::InvalidOpcode(_) | Self::BadProtocol | Self::InvalidCloseCode(_) => Some(CloseCode::ProtocolError),
+            Self::UnsupportedData => Some(CloseCode::UnsupportedData),
+            Self::InvalidUtf8 | Self::InvalidCompressedData | Self::InvalidData(_) => Some(CloseCode::InvalidPayload),
+            Self::MessageTooBig => Some(CloseCode::MessageTooBig),
+            Self::IO(_)
+            | Self::PongTimeout
+            | Self::IdleTimeout
+            | Self::Handshake(_)
+            | Self::Encode(_)
+            | Self::Closed => None,
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+pub type Packet = (Opcode, Vec<u8>);
+
+/// The data message being assembled from its fragments, kept across calls to `receive_packet` so control frames can
+/// be handled in between.
+///
+/// Bytes are read into `buf` before being decoded, so `receive_packet` can be cancelled (by a timer, say) without
+/// losing part of a frame.
+#[derive(Debug)]
+struct Fragments {
+    buf: Vec<u8>,
+    opcode: Option<Opcode>,
+    /// Whether the first frame had RSV1 set, i.e. the message is compressed with `permessage-deflate`.
+    compressed: bool,
+    data: Vec<u8>,
+    utf8: Utf8Validator,
+    max_frame_size: u64,
+    max_message_size: u64,
+    role: Role,
+    strict: bool,
+    deflate: bool,
+}
+
+impl Fragments {
+    fn new(state: &WebSocketState) -> Self {
+        Self {
+            buf: Vec::new(),
+            opcode: None,
+            compressed: false,
+            data: Vec::new(),
+            utf8: Utf8Validator::default(),
+            max_frame_size: u64::try_from(state.max_frame_size).unwrap_or(u64::MAX),
+            max_message_size: u64::try_from(state.max_message_size).unwrap_or(u64::MAX),
+            role: state.role,
+            strict: state.strict,
+            deflate: state.deflate.is_some(),
+        }
+    }
+
+    /// Checks the masking and reserved bits of a frame, which RFC 6455 requires to fail the connection.
+    fn check_bits(&self, header: &FrameHeader) -> Result<()> {
+        if !self.strict {
+            return Ok(());
+        }
+        // RSV1 is only meaningful on the first frame of a compressed message
+        let rsv1_allowed = self.deflate && self.opcode.is_none() && !header.opcode.is_control();
+        if header.mask.is_some() != self.role.expects_mask()
+            || header.rsv2
+            || header.rsv3
+            || (header.rsv1 && !rsv1_allowed)
+        {
+            return Err(Error::BadProtocol);
+        }
+        Ok(())
+    }
+
+    /// Checks the announced length of the next frame against the limits, before its payload is allocated.
+    fn check_size(&self, header: &FrameHeader) -> Result<()> {
+        let assembled = u64::try_from(self.data.len()).unwrap_or(u64::MAX);
+        if header.payload_len > self.max_frame_size
+            || assembled.saturating_add(header.payload_len) > self.max_message_size
+        {
+            return Err(Error::MessageTooBig);
+        }
+        Ok(())
+    }
+
+    /// Takes the next frame out of `buf`, once it is complete.
+    fn next_frame(&mut self) -> Result<Option<Frame>> {
+        let Some((header, header_len)) = FrameHeader::decode(&self.buf)? else {
+            return Ok(None);
+        };
+        self.check_bits(&header)?;
+        if header.opcode.is_control() {
+            if header.payload_len > 125 || !header.fin {
+                return Err(Error::BadProtocol);
+            }
+        } else {
+            self.check_size(&header)?;
+        }
+        let payload_len = usize::try_from(header.payload_len).map_err(|_overflow| Error::MessageTooBig)?;
+        let frame_len = header_len.saturating_add(payload_len);
+        if self.buf.len() < frame_len {
+            self.buf.reserve(frame_len.saturating_sub(self.buf.len()));
+            return Ok(None);
+        }
+        let mut payload = self.buf.get(header_len..frame_len).unwrap_or_default().to_vec();
+        self.buf.drain(..frame_len);
+        if let Some(mask) = header.mask {
+            apply_mask(mask, &mut payload, 0);
+        }
+        Ok(Some(Frame { header, payload }))
+    }
+}
+
+/// Receives the next control frame or complete data message, along with whether the latter is compressed.
+///
+/// Control frames are returned as soon as they arrive, even in the middle of a fragmented message, which is then
+/// resumed on the next call.
+async fn receive_packet(
+    mut stream: impl DerefMut<Target = impl AsyncReadExt + Unpin>,
+    fragments: &mut Fragments,
+) -> Result<(Packet, bool)> {
+    loop {
+        let Some(frame) = fragments.next_frame()? else {
+            if stream.read_buf(&mut fragments.buf).await? == 0 {
+                return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+            }
+            continue;
+        };
+        let header = frame.header;
+        let code = header.opcode;
+        if code.is_control() {
+            return Ok(((code, frame.payload), false));
+        }
+        match (fragments.opcode, code) {
+            (Some(_), Opcode::Continuation) if !header.rsv1 => {}
+            (None, Opcode::Continuation) | (Some(_), _) => return Err(Error::BadProtocol),
+            (None, _) => {
+                fragments.opcode = Some(code);
+                fragments.compressed = header.rsv1;
+            }
+        }
+
+        // compressed text can only be validated once inflated
+        let validate = fragments.opcode == Some(Opcode::Text) && !fragments.compressed;
+        if validate {
+            fragments.utf8.feed(&frame.payload)?;
+        }
+        fragments.data.extend(frame.payload);
+
+        if header.fin {
+            if validate {
+                fragments.utf8.finish()?;
+            }
+            let opcode = fragments.opcode.take().unwrap_or(code);
+            return Ok(((opcode, std::mem::take(&mut fragments.data)), fragments.compressed));
+        }
+    }
+}
+
+async fn send_frame(
+    mut stream: impl DerefMut<Target = impl AsyncWriteExt + Unpin>,
+    role: Role,
+    opcode: Opcode,
+    data: &[u8],
+) -> Result<()> {
+    let mut frame = Frame::new(true, opcode, data.to_vec());
+    frame.header.mask = role.mask()?;
+    frame.write(&mut *stream).await
+}
+
+/// Whether a comma separated header contains `token`, ignoring case.
+fn has_token(header: Option<&SmolStr>, token: &str) -> bool {
+    header.is_some_and(|header| header.split(",").any(|value| value.trim().eq_ignore_ascii_case(token)))
+}
+
+fn handshake_deflate(response: &Response, role: Role) -> Option<Deflate> {
+    let header = response.headers.get("Sec-WebSocket-Extensions")?;
+    let params = DeflateParams::parse_offers(header).next()?;
+    Some(match role {
+        Role::Server => Deflate::server(&params, Compression::default()),
+        Role::Client => Deflate::client(&params, Compression::default()),
+    })
+}
+
+/// Writes a single message as a sequence of fragments, so it never has to be held in memory as a whole.
+///
+/// Each fragment is written as one frame while holding the stream, so control frames sent in the meantime (from
+/// [`MessageWriter::send_control`] or from another task sharing the socket) land between fragments, as RFC 6455
+/// allows. A writer dropped before [`MessageWriter::finish`] leaves the message unterminated.
+pub struct MessageWriter<'a, W: WebSocket + ?Sized> {
+    socket: &'a W,
+    opcode: Opcode,
+    role: Role,
+    /// Set on the first fragment of a compressed message.
+    rsv1: bool,
+    fragment_size: usize,
+    buf: Vec<u8>,
+}
+
+impl<W: WebSocket + ?Sized> MessageWriter<'_, W> {
+    async fn send_fragment(&mut self, finish: bool, fragment: Vec<u8>) -> Result<()> {
+        if self.socket.state().await.is_closing() {
+            return Err(Error::Closed);
+        }
+        let mut frame = Frame::new(finish, self.opcode, fragment);
+        frame.header.rsv1 = self.rsv1;
+        frame.header.mask = self.role.mask()?;
+        frame.write(&mut *self.socket.stream_mut().await).await?;
+        self.opcode = Opcode::Continuation;
+        self.rsv1 = false;
+        Ok(())
+    }
+
+    /// Appends `data` to the message, sending every full fragment.
+    #[inline]
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<()> {
+        // keep the last bytes buffered so `finish` always has something to close the message with, and send full
+        // fragments straight from `data` otherwise
+        while !data.is_empty() {
+            if self.buf.len() == self.fragment_size {
+                let fragment = std::mem::take(&mut self.buf);
+                self.send_fragment(false, fragment).await?;
+            }
+            if self.buf.is_empty()
+                && data.len() > self.fragment_size
+                && let Some((fragment, rest)) = data.split_at_checked(self.fragment_size)
+            {
+                self.send_fragment(false, fragment.to_vec()).await?;
+                data = rest;
+                continue;
+            }
+            let len = self.fragment_size.saturating_sub(self.buf.len()).min(data.len());
+            let (head, rest) = data.split_at_checked(len).unwrap_or((data, &[]));
+            self.buf.extend_from_slice(head);
+            data = rest;
+        }
+        Ok(())
+    }
+
+    /// Sends a ping, pong or close frame in between the fragments of this message.
+    #[inline]
+    pub async fn send_control(&mut self, opcode: Opcode, payload: &[u8]) -> Result<()> {
+        if !opcode.is_control() {
+            return Err(Error::BadProtocol);
+        }
+        send_frame(self.socket.stream_mut().await, self.role, opcode, payload).await
+    }
+
+    /// Sends the remaining data as the final fragment.
+    #[inline]
+    pub async fn finish(mut self) -> Result<()> {
+        let fragment = std::mem::take(&mut self.buf);
+        self.send_fragment(true, fragment).await
+    }
+}
+
+#[derive(Debug)]
+#[expect(
+    clippy::partial_pub_fields,
+    reason = "use `..Default::default()` to initialize fields"
+)]
+pub struct WebSocketState {
+    /// When our Close frame was sent; no data may be sent from then on.
+    close_sent: Option<Instant>,
+    /// Whether `on_close` was called, which happens once per connection.
+    close_reported: bool,
+    /// How long to wait for the peer to answer our Close frame before dropping the connection.
+    pub close_timeout: Duration,
+    /// How often to ping the peer, whether or not it is sending anything.
+    pub ping_interval: Duration,
+    /// How long to wait for the pong answering a ping before giving up on the peer.
+    pub pong_timeout: Duration,
+    /// How long the peer may send nothing at all before giving up on it.
+    pub idle_timeout: Duration,
+    /// Largest payload of an outgoing frame; longer messages are fragmented.
+    pub fragment_size: usize,
+    /// Messages shorter than this are sent uncompressed even if `permessage-deflate` was negotiated.
+    pub deflate_threshold: usize,
+    /// Largest payload accepted in a single incoming frame.
+    pub max_frame_size: usize,
+    /// Largest incoming message, once its fragments are assembled and decompressed.
+    pub max_message_size: usize,
+    /// Which end of the connection this is, deciding which frames are masked.
+    pub role: Role,
+    /// Fail the connection on wrongly masked frames and on reserved bits no extension accounts for.
+    pub strict: bool,
+    deflate: Option<Deflate>,
+    protocol: Option<SmolStr>,
+    last_ping_time: Instant,
+    last_received: Instant,
+    ping_count: u64,
+    /// The payload of the ping waiting for its pong, and when it was sent.
+    pending_ping: Option<(Vec<u8>, Instant)>,
+    latencies: VecDeque<Duration>,
+}
+
+/// What to do when nothing arrives until [`WebSocketState::next_timer`].
+#[derive(Debug, Clone, Copy)]
+enum Timer {
+    Ping,
+    PongTimeout,
+    IdleTimeout,
+    CloseTimeout,
+}
+
+impl Default for WebSocketState {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(20),
+            pong_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(60),
+            fragment_size: 64 * 1024,
+            deflate_threshold: 256,
+            max_frame_size: 16 * 1024 * 1024,
+            max_message_size: 64 * 1024 * 1024,
+            role: Role::Server,
+            strict: true,
+            deflate: None,
+            protocol: None,
+            close_sent: None,
+            close_reported: false,
+            close_timeout: Duration::from_secs(5),
+            last_ping_time: Instant::now(),
+            last_received: Instant::now(),
+            ping_count: 0,
+            pending_ping: None,
+            latencies: VecDeque::new(),
+        }
+    }
+}
+
+impl WebSocketState {
+    /// Round trip times measured by the last pings, at most this many.
+    pub const LATENCY_HISTORY: usize = 16;
+
+    #[inline]
+    pub fn with_close_timeout(mut self, close_timeout: Duration) -> Self {
+        self.close_timeout = close_timeout;
+        self
+    }
+
+    /// Whether our Close frame was sent, so the connection is closing.
+    #[inline]
+    pub fn is_closing(&self) -> bool {
+        self.close_sent.is_some()
+    }
+
+    #[inline]
+    pub fn with_ping_interval(mut self, ping_interval: Duration) -> Self {
+        self.ping_interval = ping_interval;
+        self
+    }
+
+    #[inline]
+    pub fn with_pong_timeout(mut self, pong_timeout: Duration) -> Self {
+        self.pong_timeout = pong_timeout;
+        self
+    }
+
+    #[inline]
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// `fragment_size`, which can be set to 0 directly, made at least one byte long.
+    fn fragment_len(&self) -> usize {
+        self.fragment_size.max(1)
+    }
+
+    #[inline]
+    pub fn with_fragment_size(mut self, fragment_size: usize) -> Self {
+        self.fragment_size = fragment_size.max(1);
+        self
+    }
+
+    #[inline]
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    #[inline]
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    #[inline]
+    pub fn with_role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    #[inline]
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    #[inline]
+    pub fn with_deflate_threshold(mut self, deflate_threshold: usize) -> Self {
+        self.deflate_threshold = deflate_threshold;
+        self
+    }
+
+    /// Applies the subprotocol and extensions agreed on in the handshake `response`; set the role first.
+    #[inline]
+    pub fn with_handshake(mut self, response: &Response) -> Self {
+        self.deflate = handshake_deflate(response, self.role);
+        self.protocol = response.headers.get("Sec-WebSocket-Protocol").cloned();
+        self
+    }
+
+    /// The subprotocol selected during the handshake, if any.
+    #[inline]
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// The round trip time measured by the last answered ping.
+    #[inline]
+    pub fn latency(&self) -> Option<Duration> {
+        self.latencies.back().copied()
+    }
+
+    /// The round trip times measured by the last answered pings, oldest first.
+    #[inline]
+    pub fn latencies(&self) -> impl ExactSizeIterator<Item = Duration> + '_ {
+        self.latencies.iter().copied()
+    }
+
+    /// The next deadline and what to do if nothing arrives until then.
+    fn next_timer(&self) -> (Instant, Timer) {
+        if let Some(sent) = self.close_sent {
+            return (sent + self.close_timeout, Timer::CloseTimeout);
+        }
+        let idle = (self.last_received + self.idle_timeout, Timer::IdleTimeout);
+        let probe = match self.pending_ping {
+            Some((_, sent)) => (sent + self.pong_timeout, Timer::PongTimeout),
+            None => (self.last_ping_time + self.ping_interval, Timer::Ping),
+        };
+        if probe.0 < idle.0 { probe } else { idle }
+    }
+
+    /// Records a ping about to be sent and returns its payload, a sequence number matched against the pong.
+    fn start_ping(&mut self) -> Vec<u8> {
+        self.ping_count = self.ping_count.wrapping_add(1);
+        let payload = self.ping_count.to_be_bytes().to_vec();
+        self.last_ping_time = Instant::now();
+        self.pending_ping = Some((payload.clone(), self.last_ping_time));
+        payload
+    }
+
+    /// Records a pong, returning the round trip time if it answers the pending ping.
+    fn pong_received(&mut self, payload: &[u8]) -> Option<Duration> {
+        let (_, sent) = self.pending_ping.take_if(|pending| pending.0 == payload)?;
+        let latency = sent.elapsed();
+        if self.latencies.len() == Self::LATENCY_HISTORY {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+        Some(latency)
+    }
+
+    /// Compresses an outgoing message payload if it is worth it, returning whether it was.
+    fn deflate_message(&mut self, data: Vec<u8>) -> Result<(Vec<u8>, bool)> {
+        match self.deflate.as_mut() {
+            Some(deflate) if data.len() >= self.deflate_threshold => Ok((deflate.compress(&data)?, true)),
+            Some(_) | None => Ok((data, false)),
+        }
+    }
+
+    /// Decompresses an incoming message payload if it was received compressed.
+    fn inflate_message(&mut self, data: Vec<u8>, compressed: bool) -> Result<Vec<u8>> {
+        if !compressed {
+            return Ok(data);
+        }
+        match self.deflate.as_mut() {
+            Some(deflate) => deflate.decompress(&data, self.max_message_size),
+            None => Err(Error::BadProtocol),
+        }
+    }
+}
+
+pub trait WebSocket: Send + Sync {
+    type Stream: AsyncReadExt + AsyncWriteExt + Unpin;
+
+    async fn stream_mut(&self) -> impl DerefMut<Target = Self::Stream>;
+
+    async fn state(&self) -> impl Deref<Target = WebSocketState>;
+
+    async fn state_mut(&self) -> impl DerefMut<Target = WebSocketState>;
+
+    /// An error with a [`Error::close_code`] fails the connection with that code; others end `run` as they are.
+    #[inline]
+    async fn on_message(&mut self, message: Message) -> Result<()> {
+        let _ = message;
+        Ok(())
+    }
+
+    /// Called once when the connection closes: with the peer's Close frame, with ours if the peer misbehaved, or
+    /// with 1006 Abnormal if the peer never answered ours, went away without one, or timed out.
+    #[inline]
+    async fn on_close(&mut self, frame: CloseFrame) -> Result<()> {
+        let _ = frame;
+        Ok(())
+    }
+
+    #[inline]
+    async fn on_pong(&mut self, delay: Duration) -> Result<()> {
+        let _ = delay;
+        Ok(())
+    }
+}
+
+/// Calls `on_close` unless it already was.
+async fn report_close<W>(socket: &mut W, frame: CloseFrame) -> Result<()>
+where
+    W: WebSocket + ?Sized,
+{
+    let mut state = socket.state_mut().await;
+    if state.close_reported {
+        return Ok(());
+    }
+    state.close_reported = true;
+    drop(state);
+    socket.on_close(frame).await
+}
+
+pub trait WebSocketExt: WebSocket {
+    #[inline]
+    async fn send_packet(&mut self, (opcode, data): Packet) -> Result<()> {
+        if opcode.is_control() {
+            let role = self.state().await.role;
+            return send_frame(self.stream_mut().await, role, opcode, &data).await;
+        }
+        let mut writer = self.message_writer(opcode).await?;
+        let (data, compressed) = self.state_mut().await.deflate_message(data)?;
+        writer.rsv1 = compressed;
+        writer.write(&data).await?;
+        writer.finish().await
+    }
+
+    /// Starts a text or binary message to be sent in fragments of `fragment_size`.
+    ///
+    /// Messages written this way are never compressed, since their size is not known upfront. Any other opcode than
+    /// text or binary fails with [`Error::BadProtocol`], since only data frames can be fragmented.
+    #[inline]
+    async fn message_writer(&self, opcode: Opcode) -> Result<MessageWriter<'_, Self>> {
+        if !matches!(opcode, Opcode::Text | Opcode::Binary) {
+            return Err(Error::BadProtocol);
+        }
+        let state = self.state().await;
+        Ok(MessageWriter {
+            socket: self,
+            opcode,
+            role: state.role,
+            rsv1: false,
+            fragment_size: state.fragment_len(),
+            buf: Vec::new(),
+        })
+    }
+
+    #[inline]
+    async fn send_message(&mut self, message: Message) -> Result<()> {
+        self.send_packet((message.opcode(), message.into_bytes())).await
+    }
+
+    #[inline]
+    async fn send_text(&mut self, text: String) -> Result<()> {
+        self.send_packet((Opcode::Text, text.into())).await
+    }
+
+    #[inline]
+    async fn send_binary(&mut self, data: Vec<u8>) -> Result<()> {
+        self.send_packet((Opcode::Binary, data)).await
+    }
+
+    /// Starts the closing handshake; `run` then waits up to `close_timeout` for the peer's Close frame. Data can no
+    /// longer be sent, and closing again does nothing.
+    #[inline]
+    async fn send_close(&mut self, frame: CloseFrame) -> Result<()> {
+        let mut state = self.state_mut().await;
+        if state.close_sent.is_some() {
+            return Ok(());
+        }
+        state.close_sent = Some(Instant::now());
+        drop(state);
+        self.send_packet((Opcode::Close, frame.to_payload())).await
+    }
+
+    /// Closes the connection with the status code matching `error`, if the peer caused it, and returns `error`.
+    #[inline]
+    async fn fail(&mut self, error: Error) -> Result<()> {
+        if let Some(code) = error.close_code()
+            && !self.state().await.is_closing()
+        {
+            let frame = CloseFrame::new(code, error.to_string());
+            self.send_close(frame.clone()).await?;
+            report_close(self, frame).await?;
+        }
+        Err(error)
+    }
+
+    /// Pings the peer; the round trip time is reported to `on_pong` when the matching pong arrives.
+    #[inline]
+    async fn send_ping(&mut self) -> Result<()> {
+        let payload = self.state_mut().await.start_ping();
+        self.send_packet((Opcode::Ping, payload)).await
+    }
+
+    /// Handles incoming frames until the connection closes; `on_close` is called exactly once on the way out, with
+    /// 1006 Abnormal if the connection failed or timed out before a closing handshake.
+    #[inline]
+    async fn run(&mut self) -> Result<()> {
+        let result = receive_frames(self).await;
+        if let Err(error) = result.as_ref() {
+            report_close(self, CloseFrame::new(CloseCode::Abnormal, error.to_string())).await?;
+        }
+        result
+    }
+}
+
+impl<T: WebSocket> WebSocketExt for T {}
+
+/// The loop of [`WebSocketExt::run`], which reports how it ended.
+async fn receive_frames<W>(socket: &mut W) -> Result<()>
+where
+    W: WebSocketExt + ?Sized,
+{
+    let mut fragments = Fragments::new(&*socket.state().await);
+    loop {
+        let (deadline, timer) = socket.state().await.next_timer();
+        let future = receive_packet(socket.stream_mut().await, &mut fragments);
+        let ((opcode, payload), compressed) = match time::timeout_at(deadline, future).await {
+            Ok(Ok(packet)) => packet,
+            Ok(Err(error)) => return socket.fail(error).await,
+            Err(_) => {
+                match timer {
+                    Timer::Ping => socket.send_ping().await?,
+                    Timer::PongTimeout => return Err(Error::PongTimeout),
+                    Timer::IdleTimeout => return Err(Error::IdleTimeout),
+                    Timer::CloseTimeout => {
+                        report_close(socket, CloseFrame::new(CloseCode::Abnormal, "close timeout")).await?;
+                        socket.stream_mut().await.shutdown().await?;
+                        return Ok(());
+                    }
+                }
+                continue;
+            }
+        };
+        socket.state_mut().await.last_received = Instant::now();
+        let inflated = socket.state_mut().await.inflate_message(payload, compressed);
+        let data = match inflated {
+            Ok(data) => data,
+            Err(error) => return socket.fail(error).await,
+        };
+        match opcode {
+            // the application is done with the connection once it closed it
+            Opcode::Text | Opcode::Binary if socket.state().await.is_closing() => {}
+            Opcode::Text | Opcode::Binary => {
+                let handled = match Message::from_parts(opcode, data) {
+                    Ok(message) => socket.on_message(message).await,
+                    Err(error) => Err(error),
+                };
+                if let Err(error) = handled {
+                    return socket.fail(error).await;
+                }
+            }
+            Opcode::Close => {
+                let frame = match CloseFrame::from_payload(&data) {
+                    Ok(frame) => frame,
+                    Err(error) => return socket.fail(error).await,
+                };
+                let reply = CloseFrame::new(frame.code, "");
+                report_close(socket, frame).await?;
+                socket.send_close(reply).await?;
+                socket.stream_mut().await.shutdown().await?;
+                return Ok(());
+            }
+            Opcode::Ping => socket.send_packet((Opcode::Pong, data)).await?,
+            Opcode::Pong => {
+                let latency = socket.state_mut().await.pong_received(&data);
+                if let Some(latency) = latency {
+                    socket.on_pong(latency).await?;
+                }
+            }
+            #[expect(
+                clippy::unreachable,
+                reason = "Continuation frame should have been handled in receive_packet"
+            )]
+            Opcode::Continuation => unreachable!(),
+        }
+    }
+}
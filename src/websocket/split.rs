@@ -0,0 +1,288 @@
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt as _, ReadHalf, WriteHalf};
+use tokio::sync::{Notify, mpsc};
+use tokio::time;
+use tokio::time::Instant;
+
+use super::{
+    CloseCode, CloseFrame, Error, Fragments, Frame, Message, Opcode, Result, Timer, WebSocketState, receive_packet,
+};
+
+/// How many outgoing frames the senders may queue before `send` waits.
+const QUEUE_SIZE: usize = 64;
+
+#[derive(Debug)]
+enum Command {
+    Message(Message),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(CloseFrame),
+    /// Shuts the connection down once everything queued before was written.
+    Shutdown,
+}
+
+/// What the two halves share: the state, locked only between awaits, and a signal that our Close frame was sent.
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<WebSocketState>,
+    /// Wakes the receiver, so it switches to the close timeout.
+    closing: Notify,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, WebSocketState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Queues outgoing messages for the writer task to send; clone it into as many tasks as needed.
+#[derive(Debug, Clone)]
+pub struct WebSocketSender {
+    commands: mpsc::Sender<Command>,
+}
+
+impl WebSocketSender {
+    async fn queue(&self, command: Command) -> Result<()> {
+        self.commands.send(command).await.map_err(|_closed| Error::Closed)
+    }
+
+    #[inline]
+    pub async fn send(&self, message: impl Into<Message>) -> Result<()> {
+        self.queue(Command::Message(message.into())).await
+    }
+
+    /// Fails with [`Error::BadProtocol`] if `payload` is longer than the 125 bytes a control frame can carry.
+    #[inline]
+    pub async fn ping(&self, payload: Vec<u8>) -> Result<()> {
+        if payload.len() > 125 {
+            return Err(Error::BadProtocol);
+        }
+        self.queue(Command::Ping(payload)).await
+    }
+
+    #[inline]
+    pub async fn close(&self, frame: CloseFrame) -> Result<()> {
+        self.queue(Command::Close(frame)).await
+    }
+
+    /// Whether the connection was shut down or failed, so nothing queued will ever be written.
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+}
+
+/// The incoming side of a split WebSocket.
+///
+/// A task of its own writes what the [`WebSocketSender`]s queue, whether or not [`WebSocketReceiver::next`] is being
+/// awaited; it stops once the connection is shut down, or once the receiver and every sender are dropped.
+#[derive(Debug)]
+pub struct WebSocketReceiver<S> {
+    reader: ReadHalf<S>,
+    shared: Arc<Shared>,
+    fragments: Fragments,
+    /// Queues the pongs, pings and Close frames the receiver answers with, behind what was queued before.
+    commands: mpsc::Sender<Command>,
+    close_frame: Option<CloseFrame>,
+    done: bool,
+}
+
+/// Splits a WebSocket connection which completed its handshake into a cloneable sender and a receiver of incoming
+/// messages, answering pings and close frames on its own.
+///
+/// Spawns the task writing queued messages, so it must be called within a Tokio runtime.
+#[inline]
+pub fn split<S>(stream: S, state: WebSocketState) -> (WebSocketSender, WebSocketReceiver<S>)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    let (commands, queued) = mpsc::channel(QUEUE_SIZE);
+    let fragments = Fragments::new(&state);
+    let shared = Arc::new(Shared {
+        state: Mutex::new(state),
+        closing: Notify::new(),
+    });
+    tokio::spawn(write_commands(writer, Arc::clone(&shared), queued));
+    let receiver = WebSocketReceiver {
+        reader,
+        shared,
+        fragments,
+        commands: commands.clone(),
+        close_frame: None,
+        done: false,
+    };
+    (WebSocketSender { commands }, receiver)
+}
+
+/// Writes queued commands until the connection is shut down or fails, or every sender is gone.
+async fn write_commands<S>(mut writer: WriteHalf<S>, shared: Arc<Shared>, mut queued: mpsc::Receiver<Command>)
+where
+    S: AsyncWrite,
+{
+    while let Some(command) = queued.recv().await {
+        if write_command(&mut writer, &shared, command).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn write_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    shared: &Shared,
+    opcode: Opcode,
+    payload: Vec<u8>,
+) -> Result<()> {
+    let mut frame = Frame::new(true, opcode, payload);
+    frame.header.mask = shared.state().role.mask()?;
+    frame.write(writer).await
+}
+
+async fn write_command(writer: &mut (impl AsyncWrite + Unpin), shared: &Shared, command: Command) -> Result<()> {
+    match command {
+        Command::Message(message) => {
+            let opcode = message.opcode();
+            let (data, compressed, fragment_len, role) = {
+                let mut state = shared.state();
+                if state.is_closing() {
+                    return Ok(());
+                }
+                let (data, compressed) = state.deflate_message(message.into_bytes())?;
+                (data, compressed, state.fragment_len(), state.role)
+            };
+            let mut chunks = data.chunks(fragment_len).collect::<Vec<_>>();
+            if chunks.is_empty() {
+                chunks.push(&[]);
+            }
+            let last = chunks.len().saturating_sub(1);
+            for (index, chunk) in chunks.into_iter().enumerate() {
+                let opcode = if index == 0 { opcode } else { Opcode::Continuation };
+                let mut frame = Frame::new(index == last, opcode, chunk.to_vec());
+                frame.header.rsv1 = index == 0 && compressed;
+                frame.header.mask = role.mask()?;
+                frame.write(writer).await?;
+            }
+            Ok(())
+        }
+        Command::Ping(payload) => write_frame(writer, shared, Opcode::Ping, payload).await,
+        Command::Pong(payload) => write_frame(writer, shared, Opcode::Pong, payload).await,
+        Command::Close(frame) => {
+            let closed_before = {
+                let mut state = shared.state();
+                let closing = state.is_closing();
+                if !closing {
+                    state.close_sent = Some(Instant::now());
+                }
+                closing
+            };
+            if closed_before {
+                return Ok(());
+            }
+            shared.closing.notify_one();
+            write_frame(writer, shared, Opcode::Close, frame.to_payload()).await
+        }
+        Command::Shutdown => {
+            writer.shutdown().await?;
+            // nothing can be written anymore
+            Err(Error::Closed)
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> WebSocketReceiver<S> {
+    /// How the connection ended, once [`WebSocketReceiver::next`] returned `None` or an error: the peer's Close frame,
+    /// ours if the peer misbehaved, or 1006 Abnormal if it failed or timed out before a closing handshake.
+    #[inline]
+    pub fn close_frame(&self) -> Option<&CloseFrame> {
+        self.close_frame.as_ref()
+    }
+
+    #[inline]
+    pub fn state(&self) -> impl Deref<Target = WebSocketState> + '_ {
+        self.shared.state()
+    }
+
+    /// Waits for the next message; `None` once the connection is closed.
+    #[inline]
+    pub async fn next(&mut self) -> Option<Result<Message>> {
+        if self.done {
+            return None;
+        }
+        match self.receive().await {
+            Ok(Some(message)) => Some(Ok(message)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                let code = error.close_code();
+                let frame = CloseFrame::new(code.unwrap_or(CloseCode::Abnormal), error.to_string());
+                let frame = self.close_frame.get_or_insert(frame).clone();
+                if code.is_some()
+                    && let Err(close_error) = self.queue(Command::Close(frame)).await
+                {
+                    return Some(Err(close_error));
+                }
+                Some(Err(error))
+            }
+        }
+    }
+
+    async fn queue(&self, command: Command) -> Result<()> {
+        self.commands.send(command).await.map_err(|_closed| Error::Closed)
+    }
+
+    async fn receive(&mut self) -> Result<Option<Message>> {
+        loop {
+            let (deadline, timer) = self.shared.state().next_timer();
+            let packet = tokio::select! {
+                packet = receive_packet(&mut self.reader, &mut self.fragments) => Some(packet?),
+                // our Close frame was sent, so the close timeout replaces the previous timer
+                () = self.shared.closing.notified() => continue,
+                () = time::sleep_until(deadline) => None,
+            };
+            let Some(((opcode, payload), compressed)) = packet else {
+                match timer {
+                    Timer::Ping => {
+                        let payload = self.shared.state().start_ping();
+                        self.queue(Command::Ping(payload)).await?;
+                    }
+                    Timer::PongTimeout => return Err(Error::PongTimeout),
+                    Timer::IdleTimeout => return Err(Error::IdleTimeout),
+                    Timer::CloseTimeout => {
+                        self.close_frame = Some(CloseFrame::new(CloseCode::Abnormal, "close timeout"));
+                        self.queue(Command::Shutdown).await?;
+                        return Ok(None);
+                    }
+                }
+                continue;
+            };
+            let data = {
+                let mut state = self.shared.state();
+                state.last_received = Instant::now();
+                state.inflate_message(payload, compressed)?
+            };
+            match opcode {
+                Opcode::Text | Opcode::Binary if self.shared.state().is_closing() => {}
+                Opcode::Text | Opcode::Binary => return Message::from_parts(opcode, data).map(Some),
+                Opcode::Close => {
+                    let frame = CloseFrame::from_payload(&data)?;
+                    let reply = CloseFrame::new(frame.code, "");
+                    self.close_frame = Some(frame);
+                    // queued behind what the senders queued before, which is written first
+                    self.queue(Command::Close(reply)).await?;
+                    self.queue(Command::Shutdown).await?;
+                    return Ok(None);
+                }
+                Opcode::Ping => self.queue(Command::Pong(data)).await?,
+                Opcode::Pong => {
+                    self.shared.state().pong_received(&data);
+                }
+                Opcode::Continuation => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+mod common;
+
+use std::fs;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use cliud::access_log::{AccessLog, LogFormat, RotatingFile, Template};
+use cliud::http::{Request, Response};
+use cliud::service::{Exchange, Observer as _};
+
+use crate::common::{Lines, request};
+
+fn exchange<'a>(request: Option<&'a Request>, response: &'a Response, received_ms: u64) -> Exchange<'a> {
+    Exchange {
+        request,
+        response,
+        peer: "192.0.2.1:4000".parse().unwrap(),
+        received: UNIX_EPOCH + Duration::from_millis(received_ms),
+        handling: Duration::from_millis(1500),
+        writing: Duration::from_millis(250),
+        bytes_written: 100,
+    }
+}
+
+/// Logs `exchange` in `format`, and returns the line written without its newline.
+async fn render(format: LogFormat, exchange: &Exchange<'_>) -> String {
+    let lines = Lines::default();
+    let log = AccessLog::writer(lines.clone()).with_format(format);
+    log.observe(exchange).await;
+    log.flush().await;
+    let mut line = lines.text();
+    assert_eq!(line.pop(), Some('\n'), "{line}");
+    line
+}
+
+#[tokio::test]
+async fn templates_render_every_directive() {
+    let request = request("GET /path?a=1 HTTP/1.1\r\nX-In: in").await;
+    let response = Response::ok().with_header("X-Out", "out").with_body("hello");
+    let exchange = exchange(Some(&request), &response, 971_186_136_042);
+    let format = LogFormat::custom(r#"%h %a %l %u %t "%r" %m %U %q %H %s %>s %b %B %O %D %T"#);
+    assert_eq!(
+        render(format, &exchange).await,
+        r#"192.0.2.1 192.0.2.1 - - [10/Oct/2000:13:55:36 +0000] "GET /path?a=1 HTTP/1.1" GET /path ?a=1 HTTP/1.1 200 200 5 5 100 1750000 1"#
+    );
+    let format = LogFormat::custom("%{X-In}i %{x-out}o %{Missing}i %{Missing}o 100%% %z %{Bad}x %");
+    assert_eq!(render(format, &exchange).await, "in out - - 100% %z %{Bad}x %");
+}
+
+#[tokio::test]
+async fn missing_fields_are_dashes() {
+    let response = Response::new(400, "Bad Request");
+    let exchange = exchange(None, &response, 0);
+    assert_eq!(
+        render(LogFormat::custom(r#""%r" %m %U%q %H %b %B %{Referer}i"#), &exchange).await,
+        r#""-" - - - - 0 -"#
+    );
+    assert_eq!(
+        render(LogFormat::Common, &exchange).await,
+        r#"192.0.2.1 - - [01/Jan/1970:00:00:00 +0000] "-" 400 -"#
+    );
+}
+
+#[test]
+fn unsupported_directives_are_kept_as_text() {
+    assert_eq!(Template::parse("%z"), Template::parse("%%z"));
+    assert_eq!(Template::parse("a%"), Template::parse("a%%"));
+    assert_eq!(Template::parse("%{Name}x"), Template::parse("%%{Name}x"));
+    assert_eq!(Template::parse("%{Name"), Template::parse("%%{Name"));
+    assert_eq!(Template::parse("%h"), Template::parse("%a"));
+    assert_eq!(Template::parse("%s"), Template::parse("%>s"));
+    assert_ne!(Template::parse("%h"), Template::parse("%%h"));
+}
+
+#[tokio::test]
+async fn common_and_combined_formats() {
+    let request = request("POST /form HTTP/1.1\r\nReferer: https://example.com/\r\nUser-Agent: curl/8.0").await;
+    let response = Response::new(201, "Created").with_body("created");
+    let exchange = exchange(Some(&request), &response, 971_186_136_042);
+    let common = r#"192.0.2.1 - - [10/Oct/2000:13:55:36 +0000] "POST /form HTTP/1.1" 201 7"#;
+    assert_eq!(render(LogFormat::Common, &exchange).await, common);
+    assert_eq!(
+        render(LogFormat::Combined, &exchange).await,
+        format!(r#"{common} "https://example.com/" "curl/8.0""#)
+    );
+}
+
+#[tokio::test]
+async fn timestamps_follow_the_gregorian_calendar() {
+    let response = Response::ok();
+    for (millis, clf, rfc3339) in [
+        (0, "[01/Jan/1970:00:00:00 +0000]", "1970-01-01T00:00:00.000Z"),
+        (
+            971_186_136_042,
+            "[10/Oct/2000:13:55:36 +0000]",
+            "2000-10-10T13:55:36.042Z",
+        ),
+        (
+            951_782_400_000,
+            "[29/Feb/2000:00:00:00 +0000]",
+            "2000-02-29T00:00:00.000Z",
+        ),
+        (
+            1_709_208_000_000,
+            "[29/Feb/2024:12:00:00 +0000]",
+            "2024-02-29T12:00:00.000Z",
+        ),
+        (
+            4_107_542_399_999,
+            "[28/Feb/2100:23:59:59 +0000]",
+            "2100-02-28T23:59:59.999Z",
+        ),
+        (
+            4_107_542_400_000,
+            "[01/Mar/2100:00:00:00 +0000]",
+            "2100-03-01T00:00:00.000Z",
+        ),
+    ] {
+        let exchange = exchange(None, &response, millis);
+        assert_eq!(render(LogFormat::custom("%t"), &exchange).await, clf);
+        let json = render(LogFormat::Json, &exchange).await;
+        assert!(json.starts_with(&format!(r#"{{"time":"{rfc3339}","#)), "{json}");
+    }
+    // times before the epoch are clamped to it
+    let mut exchange = exchange(None, &response, 0);
+    exchange.received = UNIX_EPOCH - Duration::from_secs(1);
+    assert_eq!(
+        render(LogFormat::custom("%t"), &exchange).await,
+        "[01/Jan/1970:00:00:00 +0000]"
+    );
+}
+
+#[tokio::test]
+async fn fields_are_escaped() {
+    let request = request("GET /a%22b%0A%5C HTTP/1.1\r\nUser-Agent: say \"hi\"\t\\ \u{1}").await;
+    let response = Response::ok().with_header("X-Request-Id", "id\"1");
+    let exchange = exchange(Some(&request), &response, 0);
+
+    assert_eq!(
+        render(LogFormat::custom(r#""%r" "%{User-Agent}i""#), &exchange).await,
+        r#""GET /a\"b\x0a\\ HTTP/1.1" "say \"hi\"\x09\\ \x01""#
+    );
+    let json = render(LogFormat::Json, &exchange).await;
+    assert!(json.contains(r#""target":"/a\"b\n\\","#), "{json}");
+    assert!(json.contains(r#""user_agent":"say \"hi\"\t\\ \u0001","#), "{json}");
+    assert!(json.contains(r#""request_id":"id\"1"}"#), "{json}");
+    assert!(json.contains(r#""status":200,"#), "{json}");
+    assert!(json.contains(r#""referer":null,"#), "{json}");
+}
+
+#[tokio::test]
+async fn json_lines_without_a_request() {
+    let response = Response::new("abc", "Odd");
+    let exchange = exchange(None, &response, 0);
+    assert_eq!(
+        render(LogFormat::Json, &exchange).await,
+        concat!(
+            r#"{"time":"1970-01-01T00:00:00.000Z","remote":"192.0.2.1","method":null,"target":null,"#,
+            r#""protocol":null,"status":"abc","bytes":0,"bytes_written":100,"duration_us":1750000,"#,
+            r#""handling_us":null,"referer":null,"user_agent":null,"request_id":null}"#
+        )
+    );
+}
+
+/// A fresh directory for a test's log files.
+fn directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("cliud-access-log-{}-{name}", std::process::id()));
+    drop(fs::remove_dir_all(&directory));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn read(path: PathBuf) -> String {
+    fs::read_to_string(path).unwrap_or_default()
+}
+
+#[test]
+fn files_are_rotated() {
+    let directory = directory("rotate");
+    let path = directory.join("access.log");
+    let mut file = RotatingFile::open(&path).unwrap().with_max_size(10).with_keep(2);
+    for line in [
+        "one\n",
+        "two\n",
+        "three\n",
+        "four\n",
+        "five\n",
+        "a line longer than the limit\n",
+    ] {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+    file.flush().unwrap();
+
+    let rotated = |index: usize| directory.join(format!("access.log.{index}"));
+    assert_eq!(read(path.clone()), "a line longer than the limit\n");
+    assert_eq!(read(rotated(1)), "four\nfive\n");
+    assert_eq!(read(rotated(2)), "three\n");
+    assert!(!rotated(3).exists());
+
+    // reopening picks up the size already written
+    let mut file = RotatingFile::open(&path).unwrap().with_max_size(32).with_keep(2);
+    file.write_all(b"six\n").unwrap();
+    assert_eq!(read(path.clone()), "six\n");
+    assert_eq!(read(rotated(1)), "a line longer than the limit\n");
+    assert_eq!(read(rotated(2)), "four\nfive\n");
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn files_are_truncated_when_none_are_kept() {
+    let directory = directory("truncate");
+    let path = directory.join("access.log");
+    let mut file = RotatingFile::open(&path).unwrap().with_max_size(8).with_keep(0);
+    for line in ["one\n", "two\n", "three\n"] {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+    file.flush().unwrap();
+    assert_eq!(read(path), "three\n");
+    assert!(!directory.join("access.log.1").exists());
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn access_logs_write_to_rotating_files() {
+    let directory = directory("observe");
+    let path = directory.join("access.log");
+    let log = AccessLog::writer(RotatingFile::open(&path).unwrap()).with_format(LogFormat::custom("%s %U"));
+    let response = Response::ok();
+    for target in ["/a", "/b", "/c"] {
+        let request = request(&format!("GET {target} HTTP/1.1")).await;
+        log.observe(&exchange(Some(&request), &response, 0)).await;
+    }
+    // dropping the log waits for what is queued
+    drop(log);
+    // lines keep their order across the writer thread
+    assert_eq!(read(path), "200 /a\n200 /b\n200 /c\n");
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn flushing_waits_for_queued_lines() {
+    let lines = Lines::default();
+    let log = Arc::new(AccessLog::writer(lines.clone()).with_format(LogFormat::custom("%U")));
+    let response = Response::ok();
+    let request = request("GET /a HTTP/1.1").await;
+    // more than the queue holds, from several tasks
+    let tasks = (0..4)
+        .map(|_| {
+            let log = Arc::clone(&log);
+            let request = request.clone();
+            let response = response.clone();
+            tokio::spawn(async move {
+                for _ in 0..1000 {
+                    log.observe(&exchange(Some(&request), &response, 0)).await;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    log.flush().await;
+    assert_eq!(lines.text(), "/a\n".repeat(4000));
+}
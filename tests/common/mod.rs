@@ -0,0 +1,38 @@
+//! Helpers shared by the integration tests; each test uses what it needs.
+#![allow(dead_code, reason = "not every test uses every helper")]
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use cliud::http::Request;
+
+/// Parses a request made of `head` and no body.
+pub async fn request(head: &str) -> Request {
+    Request::try_from_buf_async(format!("{head}\r\n\r\n").as_bytes())
+        .await
+        .unwrap()
+}
+
+/// Collects what is written to it, such as an access log, to be looked at once flushed.
+#[derive(Clone, Default)]
+pub struct Lines(Arc<Mutex<Vec<u8>>>);
+
+impl Lines {
+    pub fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap_or_else(PoisonError::into_inner).clone()).unwrap()
+    }
+}
+
+impl Write for Lines {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+mod common;
+
+use std::io::{Error, ErrorKind};
+
+use async_trait::async_trait;
+use cliud::compress::{
+    CompressMiddleware, Levels, PrecompressedMiddleware, StreamEncoder, try_compress, try_decompress,
+};
+use cliud::http::{Request, Response};
+use cliud::middleware::{Middleware, Next};
+use cliud::server::Server;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+use crate::common::request;
+
+/// 1 MiB of zeros, which gzip shrinks about a thousandfold.
+fn bomb() -> Vec<u8> {
+    try_compress("gzip", &[0; 1 << 20], &Levels::default())
+        .unwrap()
+        .unwrap()
+}
+
+/// Answers with the size of the request body, as the handler sees it.
+struct BodyLength;
+
+#[async_trait]
+impl Next<Error> for BodyLength {
+    async fn call(&self, request: &Request) -> Result<Response, Error> {
+        Ok(Response::ok().plain(request.body.len().to_string()))
+    }
+}
+
+#[tokio::test]
+async fn vary_keeps_the_handler_fields() {
+    let compress = CompressMiddleware::default().with_min_size(0);
+    let request = request("GET / HTTP/1.1\r\nAccept-Encoding: gzip").await;
+
+    let next = Response::ok().plain("hello").with_header("Vary", "Origin");
+    let response = Middleware::<Error>::call(&compress, &request, &next).await.unwrap();
+    assert_eq!(response.headers.get("Content-Encoding").unwrap(), "gzip");
+    assert_eq!(response.headers.get("Vary").unwrap(), "Origin, Accept-Encoding");
+
+    let next = Response::ok().plain("hello").with_header("vary", "accept-encoding");
+    let response = Middleware::<Error>::call(&compress, &request, &next).await.unwrap();
+    assert_eq!(response.headers.get("Vary").unwrap(), "accept-encoding");
+
+    let next = Response::ok().plain("hello");
+    let response = Middleware::<Error>::call(&compress, &request, &next).await.unwrap();
+    assert_eq!(response.headers.get("Vary").unwrap(), "Accept-Encoding");
+}
+
+#[tokio::test]
+async fn refused_encodings_are_skipped() {
+    let compress = CompressMiddleware::default().with_min_size(0);
+    let next = Response::ok().plain("hello");
+
+    let request = request("GET / HTTP/1.1\r\nAccept-Encoding: gzip;q=0, deflate;q=0.5").await;
+    let response = Middleware::<Error>::call(&compress, &request, &next).await.unwrap();
+    assert_eq!(response.headers.get("Content-Encoding").unwrap(), "deflate");
+
+    let request = self::request("GET / HTTP/1.1\r\nAccept-Encoding: gzip;q=0").await;
+    let response = Middleware::<Error>::call(&compress, &request, &next).await.unwrap();
+    assert!(response.headers.get("Content-Encoding").is_none());
+    assert_eq!(response.body, b"hello");
+}
+
+#[tokio::test]
+async fn precompressed_siblings_ignore_the_query() {
+    let root = std::env::temp_dir().join(format!("cliud-precompressed-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("app.js"), "plain").unwrap();
+    std::fs::write(root.join("app.js.gz"), "gzipped").unwrap();
+
+    let precompressed = PrecompressedMiddleware::new(&root, "/file/");
+    let next = Response::not_found("no sibling");
+    let request = request("GET /file/app.js?v=3 HTTP/1.1\r\nAccept-Encoding: gzip").await;
+    let response = Middleware::<Error>::call(&precompressed, &request, &next)
+        .await
+        .unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(response.status_code, "200");
+    assert_eq!(response.body, b"gzipped");
+    assert_eq!(response.headers.get("Content-Encoding").unwrap(), "gzip");
+    assert_eq!(
+        response.headers.get("Content-Type").unwrap(),
+        "text/javascript; charset=utf-8"
+    );
+    assert_eq!(response.headers.get("Vary").unwrap(), "Accept-Encoding");
+}
+
+#[test]
+fn decompression_stops_at_the_limit() {
+    let bomb = bomb();
+    assert!(bomb.len() < 1 << 12);
+
+    let decompressed = try_decompress("gzip", &bomb, 1 << 20).unwrap().unwrap();
+    assert_eq!(decompressed.len(), 1 << 20);
+
+    let error = try_decompress("gzip", &bomb, (1 << 20) - 1).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::FileTooLarge);
+
+    assert!(try_decompress("unknown", &bomb, 1 << 20).unwrap().is_none());
+    assert!(try_decompress("gzip", b"not gzip", 1 << 20).is_err());
+}
+
+#[tokio::test]
+async fn decompressed_bodies_are_bounded() {
+    let bomb = bomb();
+    let head = format!(
+        "POST / HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+        bomb.len()
+    );
+    let mut raw = head.into_bytes();
+    raw.extend_from_slice(&bomb);
+    let request = Request::try_from_buf_async(raw.as_slice()).await.unwrap();
+    assert_eq!(request.body, bomb);
+
+    let status = async |compress: CompressMiddleware| {
+        let response = Middleware::<Error>::call(&compress, &request, &BodyLength)
+            .await
+            .unwrap();
+        (
+            response.status_code.to_string(),
+            String::from_utf8(response.body).unwrap(),
+        )
+    };
+    let unbounded = CompressMiddleware::default().with_max_ratio(usize::MAX);
+    assert_eq!(status(unbounded).await, ("200".to_owned(), (1 << 20).to_string()));
+
+    let by_size = CompressMiddleware::default().with_max_decompressed_size(1 << 16);
+    assert_eq!(status(by_size).await.0, "413");
+
+    // the default ratio of 100 is exceeded well before the default size of 16 MiB
+    assert_eq!(status(CompressMiddleware::default()).await.0, "413");
+
+    let mut corrupt = request.clone();
+    corrupt.body = b"not gzip".to_vec();
+    let response = Middleware::<Error>::call(&CompressMiddleware::default(), &corrupt, &BodyLength)
+        .await
+        .unwrap();
+    assert_eq!(response.status_code, "400");
+}
+
+#[tokio::test]
+async fn bombs_are_refused_by_the_server() {
+    let server = Server::<Error, tokio::io::DuplexStream>::new(BodyLength)
+        .with_middleware(CompressMiddleware::default())
+        .leak();
+    let exchange = async |body: &[u8]| {
+        let (mut client, stream) = tokio::io::duplex(1 << 16);
+        let connection = tokio::spawn(server.handle_connection(stream, "127.0.0.1:1".parse().unwrap()));
+        let head = format!(
+            "POST / HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        );
+        client.write_all(head.as_bytes()).await.unwrap();
+        client.write_all(body).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        connection.await.unwrap().unwrap();
+        response
+    };
+
+    let small = try_compress("gzip", b"hello", &Levels::default()).unwrap().unwrap();
+    let response = exchange(&small).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("\r\n\r\n5"), "{response}");
+
+    let response = exchange(&bomb()).await;
+    assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+}
+
+#[tokio::test]
+async fn streams_are_negotiated_only_without_a_body() {
+    let compress = CompressMiddleware::default();
+    let request = request("GET / HTTP/1.1\r\nAccept-Encoding: gzip").await;
+
+    let stream = Response::ok().with_header("Content-Type", "text/event-stream");
+    assert_eq!(compress.negotiate(&request, &stream), Some("gzip"));
+    let response = Middleware::<Error>::call(&compress, &request, &stream).await.unwrap();
+    assert_eq!(response.headers.get("Content-Encoding").unwrap(), "gzip");
+
+    let started = stream.with_body("data: already there\n\n");
+    assert_eq!(compress.negotiate(&request, &started), None);
+    let response = Middleware::<Error>::call(&compress, &request, &started).await.unwrap();
+    assert!(response.headers.get("Content-Encoding").is_none());
+    assert_eq!(response.body, b"data: already there\n\n");
+
+    let chunked = Response::ok()
+        .with_header("Transfer-Encoding", "chunked")
+        .with_body("5\r\nhello\r\n");
+    assert_eq!(compress.negotiate(&request, &chunked), None);
+}
+
+/// Compressible, but not trivially so.
+fn text(len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|index| b"abcdefghij"[index % 10] ^ u8::from(index % 7 == 0))
+        .collect()
+}
+
+/// Checks `encoding` round trips, is bounded by the limit and rejects truncated input.
+fn check_coding(encoding: &str) {
+    let data = text(1 << 20);
+    let compressed = try_compress(encoding, &data, &Levels::default()).unwrap().unwrap();
+    assert!(compressed.len() < data.len() / 10, "{encoding}");
+    assert_eq!(
+        try_decompress(encoding, &compressed, 1 << 20).unwrap().unwrap(),
+        data,
+        "{encoding}"
+    );
+
+    let error = try_decompress(encoding, &compressed, (1 << 20) - 1).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::FileTooLarge, "{encoding}");
+
+    let truncated = compressed.get(..compressed.len() / 2).unwrap();
+    assert!(try_decompress(encoding, truncated, 1 << 20).is_err(), "{encoding}");
+    let truncated = compressed.get(..compressed.len() - 1).unwrap();
+    assert!(try_decompress(encoding, truncated, 1 << 20).is_err(), "{encoding}");
+}
+
+/// Checks a body streamed through `StreamEncoder` in chunks decompresses to the whole.
+fn check_stream(encoding: &str) {
+    let data = text(100_000);
+    let mut encoder = StreamEncoder::new(encoding, &Levels::default()).unwrap().unwrap();
+    let mut compressed = Vec::new();
+    for chunk in data.chunks(30_000) {
+        let encoded = encoder.encode(chunk).unwrap();
+        // flushed, so each chunk can be sent as it comes
+        assert!(!encoded.is_empty(), "{encoding}");
+        compressed.extend_from_slice(&encoded);
+    }
+    compressed.extend_from_slice(&encoder.finish().unwrap());
+    assert_eq!(
+        try_decompress(encoding, &compressed, 1 << 20).unwrap().unwrap(),
+        data,
+        "{encoding}"
+    );
+}
+
+#[test]
+fn flate_codings() {
+    for encoding in ["gzip", "deflate", "zlib"] {
+        check_coding(encoding);
+        check_stream(encoding);
+    }
+    assert!(StreamEncoder::new("unknown", &Levels::default()).unwrap().is_none());
+}
+
+#[cfg(feature = "brotli")]
+#[test]
+fn brotli() {
+    check_coding("br");
+    check_stream("br");
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd() {
+    check_coding("zstd");
+    check_stream("zstd");
+}
+
+#[tokio::test]
+async fn only_fitting_responses_are_compressed() {
+    let compress = CompressMiddleware::default().with_min_size(0);
+    let request = request("GET / HTTP/1.1\r\nAccept-Encoding: gzip").await;
+    let text = || Response::ok().plain("hello");
+    assert_eq!(compress.negotiate(&request, &text()), Some("gzip"));
+
+    let png = text().with_header("Content-Type", "image/png");
+    assert_eq!(compress.negotiate(&request, &png), None);
+    let csv = text().with_header("Content-Type", "Text/CSV; charset=utf-8");
+    assert_eq!(compress.negotiate(&request, &csv), Some("gzip"));
+    let denying = CompressMiddleware::default().with_min_size(0).deny("text/csv");
+    assert_eq!(denying.negotiate(&request, &csv), None);
+    assert_eq!(denying.negotiate(&request, &text()), Some("gzip"));
+
+    let allowing = CompressMiddleware::default().with_min_size(0).allow("application/json");
+    let json = text().with_header("Content-Type", "application/json");
+    assert_eq!(allowing.negotiate(&request, &json), Some("gzip"));
+    assert_eq!(allowing.negotiate(&request, &text()), None);
+    // the deny list wins
+    let both = allowing.deny("application/json");
+    assert_eq!(both.negotiate(&request, &json), None);
+
+    let no_transform = text().with_header("Cache-Control", "public, No-Transform");
+    assert_eq!(compress.negotiate(&request, &no_transform), None);
+    let encoded = text().with_header("Content-Encoding", "br");
+    assert_eq!(compress.negotiate(&request, &encoded), None);
+    let response = Middleware::<Error>::call(&compress, &request, &encoded).await.unwrap();
+    assert_eq!(response.headers.get("Content-Encoding").unwrap(), "br");
+    assert_eq!(response.body, b"hello");
+}
+
+#[tokio::test]
+async fn bodiless_responses_are_left_alone() {
+    let compress = CompressMiddleware::default().with_min_size(0);
+    let get = request("GET / HTTP/1.1\r\nAccept-Encoding: gzip").await;
+    for (status, description) in [
+        (100, "Continue"),
+        (101, "Switching Protocols"),
+        (204, "No Content"),
+        (304, "Not Modified"),
+    ] {
+        let response = Response::new(status, description);
+        assert_eq!(compress.negotiate(&get, &response), None, "{status}");
+        let response = Middleware::<Error>::call(&compress, &get, &response).await.unwrap();
+        assert!(response.headers.get("Content-Encoding").is_none(), "{status}");
+        assert!(response.body.is_empty(), "{status}");
+    }
+
+    let head = request("HEAD / HTTP/1.1\r\nAccept-Encoding: gzip").await;
+    let response = Response::ok().plain("hello");
+    assert_eq!(compress.negotiate(&head, &response), None);
+    assert_eq!(compress.negotiate(&get, &response), Some("gzip"));
+}
@@ -0,0 +1,71 @@
+use std::io::{self, ErrorKind};
+
+use cliud::http::{Error, Request, Response};
+use cliud::server::Server;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _, DuplexStream};
+
+#[tokio::test]
+async fn bodies_are_read_up_to_their_length() {
+    let raw = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n";
+    let mut reader = raw.as_slice();
+    let request = Request::try_from_buf_async(&mut reader).await.unwrap();
+    assert_eq!(request.body, b"hello");
+    assert_eq!(reader, b"GET / HTTP/1.1\r\n\r\n");
+}
+
+#[tokio::test]
+async fn short_bodies_fail() {
+    let raw = b"POST / HTTP/1.1\r\nContent-Length: 1000000000000\r\n\r\ntiny";
+    let result = Request::try_from_buf_async_with_limit(raw.as_slice(), usize::MAX).await;
+    assert!(matches!(result, Err(Error::IO(error)) if error.kind() == ErrorKind::UnexpectedEof));
+}
+
+#[tokio::test]
+async fn large_bodies_are_refused_before_being_read() {
+    let raw = b"POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nhello!";
+    assert!(matches!(
+        Request::try_from_buf_async_with_limit(raw.as_slice(), 5).await,
+        Err(Error::PayloadTooLarge(6))
+    ));
+    assert_eq!(
+        Request::try_from_buf_async_with_limit(raw.as_slice(), 6)
+            .await
+            .unwrap()
+            .body,
+        b"hello!"
+    );
+}
+
+/// Sends `raw`, and returns everything the server answers until it closes the connection.
+async fn exchange(server: &'static Server<io::Error, DuplexStream>, raw: &[u8], shutdown: bool) -> String {
+    let (mut client, stream) = tokio::io::duplex(1 << 16);
+    let connection = tokio::spawn(server.handle_connection(stream, "127.0.0.1:1".parse().unwrap()));
+    client.write_all(raw).await.unwrap();
+    if shutdown {
+        client.shutdown().await.unwrap();
+    }
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    connection.await.unwrap().unwrap();
+    response
+}
+
+#[tokio::test]
+async fn the_server_answers_huge_lengths_with_413() {
+    let server = Server::new(Response::ok()).leak();
+    // the connection is closed without waiting for the rest of the body
+    let response = exchange(
+        server,
+        b"POST / HTTP/1.1\r\nContent-Length: 1000000000000\r\n\r\ntiny",
+        false,
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"), "{response}");
+    assert!(response.contains("Connection: close\r\n"), "{response}");
+
+    let server = Server::new(Response::ok()).with_max_body_size(4).leak();
+    let response = exchange(server, b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello", false).await;
+    assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+    let response = exchange(server, b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nhell", true).await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+}
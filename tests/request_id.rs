@@ -0,0 +1,127 @@
+mod common;
+
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use cliud::access_log::{AccessLog, LogFormat};
+use cliud::http::{Request, Response};
+use cliud::middleware::{Middleware, Next, RequestIdMiddleware};
+use cliud::server::Server;
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _, DuplexStream};
+
+use crate::common::{Lines, request};
+
+/// Takes a while, and answers with the ID and start it was handed, and timing of its own.
+struct Handler;
+
+#[async_trait]
+impl Next<io::Error> for Handler {
+    async fn call(&self, _request: &Request) -> io::Result<Response> {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let start = RequestIdMiddleware::start().unwrap();
+        let since_start = SystemTime::now().duration_since(start).unwrap();
+        Ok(Response::ok()
+            .with_header("Server-Timing", "db;dur=1")
+            .with_header("X-Seen-Id", RequestIdMiddleware::id().unwrap())
+            .with_header("X-Since-Start", since_start.as_micros()))
+    }
+}
+
+async fn respond(middleware: &RequestIdMiddleware, request: &Request) -> Response {
+    Middleware::<io::Error>::call(middleware, request, &Handler)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn requests_are_tagged_and_timed() {
+    let response = respond(&RequestIdMiddleware::new(), &request("GET / HTTP/1.1").await).await;
+    let id = response.headers.get(RequestIdMiddleware::HEADER).unwrap();
+    assert_eq!(id.len(), 32);
+    assert_eq!(response.headers.get("X-Seen-Id"), Some(id));
+
+    let duration = RequestIdMiddleware::duration(&response).unwrap();
+    let since_start: u128 = response.headers.get("X-Since-Start").unwrap().parse().unwrap();
+    assert!(duration >= Duration::from_millis(5), "{duration:?}");
+    assert!(duration.as_micros() + 1 >= since_start, "{duration:?} {since_start}");
+    assert!(RequestIdMiddleware::end(&response).unwrap() <= SystemTime::now());
+    assert!(RequestIdMiddleware::id().is_none());
+
+    // the timing stays on the server unless asked for
+    assert_eq!(response.headers.get("Server-Timing").unwrap(), "db;dur=1");
+    let written = String::from_utf8(response.to_bytes()).unwrap();
+    assert!(
+        !written.contains("X-Request-Start") && !written.contains("X-Request-End"),
+        "{written}"
+    );
+
+    let middleware = RequestIdMiddleware::new().with_server_timing(true);
+    let response = respond(&middleware, &request("GET / HTTP/1.1").await).await;
+    let duration = RequestIdMiddleware::duration(&response).unwrap();
+    let timing = response.headers.get("Server-Timing").unwrap();
+    let total = timing.strip_prefix("db;dur=1, total;dur=").unwrap();
+    let (millis, micros) = total.split_once('.').unwrap();
+    assert_eq!(millis.parse::<u128>().unwrap(), duration.as_millis());
+    assert_eq!(micros.len(), 3);
+}
+
+#[tokio::test]
+async fn incoming_ids_are_only_kept_when_trusted() {
+    let head = "GET / HTTP/1.1\r\nX-Request-Id: from-proxy\r\nX-Request-Start: t=0";
+    let untrusted = respond(&RequestIdMiddleware::new(), &request(head).await).await;
+    assert_ne!(untrusted.headers.get("X-Seen-Id").unwrap(), "from-proxy");
+    // the start is always the middleware's own
+    assert!(RequestIdMiddleware::end(&untrusted).unwrap() > SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+    assert!(RequestIdMiddleware::duration(&untrusted).unwrap() < Duration::from_secs(60));
+
+    let trusting = RequestIdMiddleware::new().with_trust_incoming(true);
+    let trusted = respond(&trusting, &request(head).await).await;
+    assert_eq!(trusted.headers.get("X-Seen-Id").unwrap(), "from-proxy");
+    assert_eq!(trusted.headers.get(RequestIdMiddleware::HEADER).unwrap(), "from-proxy");
+
+    for forged in ["", "two words", "line\u{7f}"] {
+        let head = format!("GET / HTTP/1.1\r\nX-Request-Id: {forged}");
+        let response = respond(&trusting, &request(&head).await).await;
+        assert_eq!(response.headers.get("X-Seen-Id").unwrap().len(), 32, "{forged:?}");
+    }
+    let long = format!(
+        "GET / HTTP/1.1\r\nX-Request-Id: {}",
+        "a".repeat(RequestIdMiddleware::MAX_LEN + 1)
+    );
+    let response = respond(&trusting, &request(&long).await).await;
+    assert_eq!(response.headers.get("X-Seen-Id").unwrap().len(), 32);
+}
+
+#[tokio::test]
+async fn the_access_log_reports_the_handling_time() {
+    let lines = Lines::default();
+    let log = Arc::new(
+        AccessLog::writer(lines.clone())
+            .with_format(LogFormat::Json)
+            .with_color(false),
+    );
+    let server: &'static Server<io::Error, DuplexStream> = Server::new(Handler)
+        .with_middleware(RequestIdMiddleware::new())
+        .with_observer(Arc::clone(&log))
+        .leak();
+    let (mut stream, server_stream) = tokio::io::duplex(1 << 16);
+    let connection = tokio::spawn(server.handle_connection(server_stream, "127.0.0.1:1".parse().unwrap()));
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    connection.await.unwrap().unwrap();
+
+    log.flush().await;
+    let line = lines.text();
+    let handling: u128 = line
+        .split_once(r#""handling_us":"#)
+        .and_then(|(_, rest)| rest.split_once(','))
+        .unwrap()
+        .0
+        .parse()
+        .unwrap();
+    assert!(handling >= 5000, "{line}");
+}
@@ -0,0 +1,149 @@
+use std::io::Read as _;
+use std::time::Duration;
+
+use cliud::compress::Levels;
+use cliud::sse::{self, Closed, Event, EventStream};
+use tokio::io::{AsyncReadExt as _, DuplexStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+fn framed(event: &Event) -> String {
+    String::from_utf8(event.to_bytes()).unwrap()
+}
+
+#[test]
+fn data_is_split_on_every_line_ending() {
+    assert_eq!(framed(&Event::new("")), "data: \n\n");
+    assert_eq!(framed(&Event::new("a")), "data: a\n\n");
+    assert_eq!(
+        framed(&Event::new("a\nb\r\nc\rd")),
+        "data: a\ndata: b\ndata: c\ndata: d\n\n"
+    );
+    assert_eq!(framed(&Event::new("a\n")), "data: a\ndata: \n\n");
+    assert_eq!(framed(&Event::new("\r\n\r\n")), "data: \ndata: \ndata: \n\n");
+}
+
+#[test]
+fn fields_cannot_be_injected() {
+    assert_eq!(
+        framed(&Event::new("x\rid: injected")),
+        "data: x\ndata: id: injected\n\n"
+    );
+    let event = Event::new("x").with_event("a\rb\nc").with_id("1\r\n2\0");
+    assert_eq!(framed(&event), "event: abc\nid: 12\ndata: x\n\n");
+}
+
+async fn stream_output(levels: &Levels, send: impl AsyncFnOnce(&mut EventStream<tokio::io::DuplexStream>)) -> Vec<u8> {
+    let (mut client, server) = tokio::io::duplex(1 << 16);
+    let response = sse::response().with_header("Content-Encoding", "gzip");
+    let mut stream = EventStream::new(server, &response, levels).unwrap();
+    send(&mut stream).await;
+    stream.finish().await.unwrap();
+    let mut output = Vec::new();
+    client.read_to_end(&mut output).await.unwrap();
+    output
+}
+
+#[tokio::test]
+async fn comments_are_split_on_every_line_ending() {
+    let stored = Levels {
+        gzip: 0,
+        ..Levels::default()
+    };
+    let output = stream_output(&stored, async |stream| {
+        stream.comment("").await.unwrap();
+        stream.comment("a\rdata: b\n").await.unwrap();
+    })
+    .await;
+    let mut decoded = String::new();
+    flate2::read::GzDecoder::new(output.as_slice())
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, ":\n: a\n: data: b\n: \n");
+}
+
+#[tokio::test]
+async fn streams_compress_with_the_given_levels() {
+    let event = Event::new("hello hello hello hello");
+    let send = async |stream: &mut EventStream<_>| stream.send(&event).await.unwrap();
+
+    // level 0 stores the data as is
+    let stored = stream_output(
+        &Levels {
+            gzip: 0,
+            ..Levels::default()
+        },
+        send,
+    )
+    .await;
+    assert!(stored.windows(11).any(|window| window == b"data: hello"));
+
+    let compressed = stream_output(&Levels::default(), send).await;
+    assert!(!compressed.windows(11).any(|window| window == b"data: hello"));
+
+    for output in [stored, compressed] {
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(output.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "data: hello hello hello hello\n\n");
+    }
+}
+
+/// Forwards what is sent on the returned sender to the returned client, uncompressed, with a heartbeat of 10 seconds.
+fn forward() -> (DuplexStream, mpsc::Sender<Event>, JoinHandle<Closed>) {
+    let (client, server) = tokio::io::duplex(1 << 16);
+    let (events, received) = mpsc::channel(8);
+    let mut stream = EventStream::new(server, &sse::response(), &Levels::default())
+        .unwrap()
+        .with_heartbeat(Duration::from_secs(10));
+    let task = tokio::spawn(async move {
+        let closed = stream.forward(received).await.unwrap();
+        stream.finish().await.unwrap();
+        closed
+    });
+    (client, events, task)
+}
+
+async fn read(client: &mut DuplexStream) -> String {
+    let mut buf = [0; 256];
+    let len = client.read(&mut buf).await.unwrap();
+    String::from_utf8(buf.get(..len).unwrap().to_vec()).unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn quiet_streams_get_heartbeats() {
+    let (mut client, events, _task) = forward();
+    let start = Instant::now();
+    assert_eq!(read(&mut client).await, ":\n");
+    assert_eq!(start.elapsed(), Duration::from_secs(10));
+    assert_eq!(read(&mut client).await, ":\n");
+    assert_eq!(start.elapsed(), Duration::from_secs(20));
+
+    // an event postpones the next heartbeat
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    events.send(Event::new("x")).await.unwrap();
+    assert_eq!(read(&mut client).await, "data: x\n\n");
+    assert_eq!(read(&mut client).await, ":\n");
+    assert_eq!(start.elapsed(), Duration::from_secs(35));
+}
+
+#[tokio::test(start_paused = true)]
+async fn forwarding_stops_when_the_client_goes_away() {
+    let (client, _events, task) = forward();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    drop(client);
+    assert_eq!(task.await.unwrap(), Closed::Client);
+}
+
+#[tokio::test(start_paused = true)]
+async fn forwarding_stops_when_the_source_runs_dry() {
+    let (mut client, events, task) = forward();
+    events.send(Event::new("last")).await.unwrap();
+    drop(events);
+    assert_eq!(task.await.unwrap(), Closed::Source);
+    let mut rest = String::new();
+    client.read_to_string(&mut rest).await.unwrap();
+    assert_eq!(rest, "data: last\n\n");
+}
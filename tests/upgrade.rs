@@ -0,0 +1,78 @@
+use std::io;
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+use cliud::http::{Request, Response};
+use cliud::server::Server;
+use cliud::service::Service;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _, BufReader, DuplexStream};
+
+/// Sent in the same write as the request, so the server has read it into its buffer before handing the connection
+/// over.
+const REQUEST: &[u8] = b"GET /tunnel HTTP/1.1\r\nHost: localhost\r\n\r\nearly";
+
+/// Runs `server` on one connection, sends `REQUEST` and then `late`, and returns what came back after the response
+/// head.
+async fn exchange(server: &'static Server<io::Error, DuplexStream>, late: &[u8]) -> Vec<u8> {
+    let (mut stream, server_stream) = tokio::io::duplex(1 << 16);
+    let connection = tokio::spawn(server.handle_connection(server_stream, "127.0.0.1:1".parse().unwrap()));
+    stream.write_all(REQUEST).await.unwrap();
+    stream.write_all(late).await.unwrap();
+
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    connection.await.unwrap().unwrap();
+    let end = received.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+    received.split_off(end + 4)
+}
+
+async fn echo(mut stream: impl AsyncRead + AsyncWrite + Unpin) -> io::Result<()> {
+    let mut data = [0; 10];
+    stream.read_exact(&mut data).await?;
+    stream.write_all(&data).await
+}
+
+#[tokio::test]
+async fn upgrades_receive_the_buffered_bytes() {
+    let server = Server::new(
+        Response::new(101, "Switching Protocols")
+            .with_header("Connection", "Upgrade")
+            .with_header("Upgrade", "echo")
+            .with_upgrade(|upgraded| async move {
+                assert!(upgraded.buffered().starts_with(b"early"));
+                Ok(echo(upgraded).await?)
+            }),
+    )
+    .leak();
+    assert_eq!(exchange(server, b"late!").await, b"earlylate!");
+}
+
+struct Echo;
+
+#[async_trait]
+impl Service<io::Error, DuplexStream> for Echo {
+    fn accepts(&self, _request: &Request, response: &Response) -> bool {
+        response
+            .headers
+            .get("Content-Type")
+            .is_some_and(|value| value == "application/x-echo")
+    }
+
+    async fn call(
+        &self,
+        _request: &Request,
+        _response: &Response,
+        _peer: &SocketAddr,
+        stream: &mut BufReader<DuplexStream>,
+    ) -> io::Result<()> {
+        echo(stream).await
+    }
+}
+
+#[tokio::test]
+async fn services_receive_the_buffered_bytes() {
+    let server = Server::new(Response::ok().with_header("Content-Type", "application/x-echo"))
+        .with_service(Echo)
+        .leak();
+    assert_eq!(exchange(server, b"late!").await, b"earlylate!");
+}
@@ -0,0 +1,148 @@
+use std::io;
+
+use cliud::http::{Request, Response};
+use cliud::middleware::Middleware;
+use cliud::server::Server;
+use cliud::websocket::client::{ClientHandshake, generate_key};
+use cliud::websocket::{DeflateConfig, Error, Frame, Opcode, Role, WebSocketHandshakeMiddleware};
+use tokio::io::DuplexStream;
+
+async fn respond(middleware: &WebSocketHandshakeMiddleware, request: &Request) -> Response {
+    let next = Response::not_found("not a WebSocket");
+    Middleware::<io::Error>::call(middleware, request, &next).await.unwrap()
+}
+
+fn failure(result: cliud::websocket::Result<()>) -> String {
+    match result {
+        Err(Error::Handshake(reason)) => reason.to_string(),
+        other => panic!("expected a handshake failure, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn accepted_handshakes_verify() {
+    let middleware = WebSocketHandshakeMiddleware::new()
+        .with_protocols(["chat"])
+        .with_deflate(DeflateConfig::default());
+    for client in [
+        ClientHandshake::new("localhost", "/ws"),
+        ClientHandshake::new("localhost", "/ws").with_protocols(["superchat", "chat"]),
+        ClientHandshake::new("localhost", "/ws").with_deflate(true),
+    ] {
+        let key = generate_key().unwrap();
+        let response = respond(&middleware, &client.request(&key)).await;
+        assert_eq!(response.status_code, "101");
+        client.verify(&key, &response).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn responses_agreeing_to_anything_else_fail() {
+    let middleware = WebSocketHandshakeMiddleware::new()
+        .with_protocols(["chat"])
+        .with_deflate(DeflateConfig::default());
+    let client = ClientHandshake::new("localhost", "/ws").with_protocols(["chat"]);
+    let key = generate_key().unwrap();
+    let response = respond(&middleware, &client.request(&key)).await;
+    client.verify(&key, &response).unwrap();
+
+    let other_key = generate_key().unwrap();
+    assert_eq!(
+        failure(client.verify(&other_key, &response)),
+        "wrong Sec-WebSocket-Accept"
+    );
+
+    let mut missing = response.clone();
+    missing.headers.remove("Sec-WebSocket-Accept");
+    assert_eq!(failure(client.verify(&key, &missing)), "wrong Sec-WebSocket-Accept");
+
+    let other_protocol = response.clone().with_header("Sec-WebSocket-Protocol", "superchat");
+    assert_eq!(
+        failure(client.verify(&key, &other_protocol)),
+        "subprotocol was not offered"
+    );
+
+    let extension = response
+        .clone()
+        .with_header("Sec-WebSocket-Extensions", "permessage-deflate");
+    assert_eq!(failure(client.verify(&key, &extension)), "extension was not offered");
+
+    let deflate = client.clone().with_deflate(true);
+    for extensions in [
+        "permessage-deflate; client_max_window_bits=10",
+        "permessage-deflate, permessage-deflate",
+        "x-unknown",
+    ] {
+        let response = response.clone().with_header("Sec-WebSocket-Extensions", extensions);
+        assert_eq!(
+            failure(deflate.verify(&key, &response)),
+            "extension was not offered",
+            "{extensions}"
+        );
+    }
+
+    let mut no_upgrade = response.clone();
+    no_upgrade.headers.remove("Upgrade");
+    assert_eq!(failure(client.verify(&key, &no_upgrade)), "missing upgrade headers");
+}
+
+#[tokio::test]
+async fn refused_upgrades_fail() {
+    let client = ClientHandshake::new("localhost", "/ws").with_header("Origin", "https://evil.example");
+    let key = generate_key().unwrap();
+
+    let middleware = WebSocketHandshakeMiddleware::new().with_allowed_origins(["https://good.example"]);
+    let response = respond(&middleware, &client.request(&key)).await;
+    assert_eq!(
+        failure(client.verify(&key, &response)),
+        "unexpected status HTTP/1.1 403 Forbidden"
+    );
+
+    let mut old = client.request(&key);
+    old.headers.insert("Sec-WebSocket-Version", "8");
+    let response = respond(&WebSocketHandshakeMiddleware::new(), &old).await;
+    assert_eq!(response.status_code, "426");
+    assert!(failure(client.verify(&key, &response)).starts_with("unexpected status"));
+}
+
+fn server(middleware: WebSocketHandshakeMiddleware) -> &'static Server<io::Error, DuplexStream> {
+    Server::new(Response::not_found("not a WebSocket"))
+        .with_middleware(middleware)
+        .leak()
+}
+
+#[tokio::test]
+async fn handshakes_are_performed_over_a_stream() {
+    // greets right after the handshake, so the frame arrives together with the response head
+    let middleware = WebSocketHandshakeMiddleware::new()
+        .with_protocols(["chat"])
+        .with_handler(|mut upgraded, _state| async move {
+            Frame::new(true, Opcode::Text, b"welcome".to_vec())
+                .write(&mut upgraded)
+                .await?;
+            Ok(())
+        });
+    let (mut stream, server_stream) = tokio::io::duplex(1 << 16);
+    let connection = tokio::spawn(server(middleware).handle_connection(server_stream, "127.0.0.1:1".parse().unwrap()));
+
+    let client = ClientHandshake::new("localhost", "/ws").with_protocols(["chat"]);
+    let state = client.perform(&mut stream).await.unwrap();
+    assert_eq!(state.role, Role::Client);
+    assert_eq!(state.protocol(), Some("chat"));
+
+    let frame = Frame::read(&mut stream).await.unwrap();
+    assert_eq!(frame.payload, b"welcome");
+    connection.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn failed_handshakes_are_reported() {
+    let server = Server::<io::Error, DuplexStream>::new(Response::new(404, "Not Found")).leak();
+    let (mut stream, server_stream) = tokio::io::duplex(1 << 16);
+    let connection = tokio::spawn(server.handle_connection(server_stream, "127.0.0.1:1".parse().unwrap()));
+
+    let result = ClientHandshake::new("localhost", "/ws").perform(&mut stream).await;
+    assert_eq!(failure(result.map(drop)), "unexpected status HTTP/1.1 404 Not Found");
+    drop(stream);
+    connection.await.unwrap().unwrap();
+}
@@ -0,0 +1,175 @@
+use std::ops::{Deref, DerefMut};
+
+#[cfg(feature = "json")]
+use cliud::websocket::JsonLines;
+use cliud::websocket::{
+    Codec, Error, Frame, LengthPrefixed, Message, Opcode, Result, TypedWebSocket, WebSocket, WebSocketState,
+};
+use tokio::io::DuplexStream;
+use tokio::sync::{Mutex, RwLock};
+
+#[cfg(feature = "json")]
+#[test]
+fn json_lines_round_trip() {
+    type Item = Vec<(String, Option<u32>)>;
+    let item: Item = vec![("a\nb".to_owned(), Some(1)), ("c".to_owned(), None)];
+    let message = Codec::<Item>::encode(&JsonLines, &item).unwrap();
+    assert_eq!(message, Message::Text("[[\"a\\nb\",1],[\"c\",null]]\n".to_owned()));
+    assert_eq!(Codec::<Item>::decode(&JsonLines, message).unwrap(), item);
+
+    // the trailing newline is optional
+    let decoded: Item = JsonLines.decode(Message::Text("[[\"d\",2]]".to_owned())).unwrap();
+    assert_eq!(decoded, [("d".to_owned(), Some(2))]);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_lines_failures() {
+    type Item = Vec<u32>;
+    let decode = |message| Codec::<Item>::decode(&JsonLines, message);
+    assert!(matches!(
+        decode(Message::Binary(b"[1]".to_vec())),
+        Err(Error::UnsupportedData)
+    ));
+    assert!(matches!(
+        decode(Message::Text("[1,".to_owned())),
+        Err(Error::InvalidData(_))
+    ));
+    assert!(matches!(
+        decode(Message::Text("{\"a\":1}".to_owned())),
+        Err(Error::InvalidData(_))
+    ));
+    assert!(matches!(
+        decode(Message::Text("[1]\n[2]\n".to_owned())),
+        Err(Error::InvalidData(_))
+    ));
+}
+
+#[test]
+fn length_prefixed_round_trip() {
+    for payload in [Vec::new(), b"hello".to_vec(), vec![0xab; 70_000]] {
+        let message = LengthPrefixed.encode(&payload).unwrap();
+        let Message::Binary(data) = &message else {
+            panic!("expected a binary message, got {message:?}");
+        };
+        assert_eq!(
+            data.get(..4),
+            Some(u32::try_from(payload.len()).unwrap().to_be_bytes().as_slice())
+        );
+        assert_eq!(Codec::<Vec<u8>>::decode(&LengthPrefixed, message).unwrap(), payload);
+    }
+    let fixed: [u8; 3] = LengthPrefixed
+        .decode(Message::Binary(vec![0, 0, 0, 3, 1, 2, 3]))
+        .unwrap();
+    assert_eq!(fixed, [1, 2, 3]);
+}
+
+#[test]
+fn length_prefixed_failures() {
+    let decode = |message| Codec::<Vec<u8>>::decode(&LengthPrefixed, message);
+    let invalid = |message| match decode(message) {
+        Err(Error::InvalidData(reason)) => reason.to_string(),
+        other => panic!("expected invalid data, got {other:?}"),
+    };
+    assert!(matches!(
+        decode(Message::Text("hello".to_owned())),
+        Err(Error::UnsupportedData)
+    ));
+    assert_eq!(invalid(Message::Binary(vec![0, 0, 0])), "missing length prefix");
+    assert_eq!(invalid(Message::Binary(vec![0, 0, 0, 2, 1])), "length prefix mismatch");
+    assert_eq!(
+        invalid(Message::Binary(vec![0, 0, 0, 1, 1, 2])),
+        "length prefix mismatch"
+    );
+    assert!(matches!(
+        Codec::<[u8; 3]>::decode(&LengthPrefixed, Message::Binary(vec![0, 0, 0, 2, 1, 2])),
+        Err(Error::InvalidData(_))
+    ));
+}
+
+/// Answers every item with its bytes reversed.
+struct Reverse {
+    stream: Mutex<DuplexStream>,
+    state: RwLock<WebSocketState>,
+}
+
+impl WebSocket for Reverse {
+    type Stream = DuplexStream;
+
+    async fn state(&self) -> impl Deref<Target = WebSocketState> {
+        self.state.read().await
+    }
+
+    async fn state_mut(&self) -> impl DerefMut<Target = WebSocketState> {
+        self.state.write().await
+    }
+
+    async fn stream_mut(&self) -> impl DerefMut<Target = Self::Stream> {
+        self.stream.lock().await
+    }
+
+    async fn on_message(&mut self, message: Message) -> Result<()> {
+        panic!("run_typed handed {message:?} to on_message")
+    }
+}
+
+impl TypedWebSocket for Reverse {
+    type Item = Vec<u8>;
+    type Codec = LengthPrefixed;
+
+    fn codec(&self) -> &Self::Codec {
+        &LengthPrefixed
+    }
+
+    async fn on_item(&mut self, mut item: Vec<u8>) -> Result<()> {
+        item.reverse();
+        self.send_typed(&item).await
+    }
+}
+
+/// Sends `frames` as a client to a `Reverse` session, and returns the first two frames it answers with.
+async fn exchange(frames: &[(Opcode, &[u8])]) -> (Frame, Frame) {
+    let (mut peer, stream) = tokio::io::duplex(1 << 16);
+    let mut session = Reverse {
+        stream: Mutex::new(stream),
+        state: RwLock::new(WebSocketState::default()),
+    };
+    let running = tokio::spawn(async move { session.run_typed().await });
+    for &(opcode, payload) in frames {
+        let mut frame = Frame::new(true, opcode, payload.to_vec());
+        frame.header.mask = Some([1, 2, 3, 4]);
+        frame.write(&mut peer).await.unwrap();
+    }
+    let answers = (
+        Frame::read(&mut peer).await.unwrap(),
+        Frame::read(&mut peer).await.unwrap(),
+    );
+    running.abort();
+    answers
+}
+
+fn close_code(frame: &Frame) -> Option<u16> {
+    (frame.header.opcode == Opcode::Close).then(|| u16::from_be_bytes(*frame.payload.first_chunk().unwrap()))
+}
+
+#[tokio::test]
+async fn sessions_receive_decoded_items() {
+    let (first, second) = exchange(&[
+        (Opcode::Binary, &[0, 0, 0, 3, 1, 2, 3]),
+        (Opcode::Binary, &[0, 0, 0, 0]),
+    ])
+    .await;
+    assert_eq!(first.payload, [0, 0, 0, 3, 3, 2, 1]);
+    assert_eq!(second.payload, [0, 0, 0, 0]);
+}
+
+#[tokio::test]
+async fn undecodable_messages_fail_the_connection() {
+    let (echo, close) = exchange(&[(Opcode::Binary, &[0, 0, 0, 1, 9]), (Opcode::Text, b"text")]).await;
+    assert_eq!(echo.payload, [0, 0, 0, 1, 9]);
+    assert_eq!(close_code(&close), Some(1003));
+
+    let (echo, close) = exchange(&[(Opcode::Binary, &[0, 0, 0, 1, 9]), (Opcode::Binary, &[0, 0, 0, 9])]).await;
+    assert_eq!(echo.payload, [0, 0, 0, 1, 9]);
+    assert_eq!(close_code(&close), Some(1007));
+}
@@ -0,0 +1,887 @@
+//! Cases modelled on the Autobahn TestSuite fuzzing client, run offline against cliud echo servers over
+//! `tokio::io::duplex`. Every case runs against a server built on `WebSocketExt::run` and one built on `split`, with
+//! the frames written whole and trickled in small chunks. Run with `--nocapture` for the per-case report.
+
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+use cliud::http::Response;
+use cliud::websocket::{
+    Deflate, DeflateParams, Error, Frame, Message, Opcode, Result, WebSocket, WebSocketExt as _, WebSocketState, split,
+};
+use flate2::Compression;
+use tokio::io::{AsyncWriteExt as _, DuplexStream, ReadHalf};
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::timeout;
+
+const FIN: u8 = 0x80;
+const RSV1: u8 = 0x40;
+const RSV2: u8 = 0x20;
+const RSV3: u8 = 0x10;
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// How long to wait for each frame the server should send.
+const PATIENCE: Duration = Duration::from_secs(5);
+
+/// Encodes a masked client frame from its first header byte, independently of the encoder under test, so invalid
+/// frames can be sent as well.
+fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x5a, 0x13, 0xc7, 0x88];
+    let mut wire = vec![first];
+    match payload.len() {
+        len @ 0..=125 => wire.push(0x80 | len as u8),
+        len @ 126..=0xffff => {
+            wire.push(0x80 | 126);
+            wire.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            wire.push(0x80 | 127);
+            wire.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    wire.extend_from_slice(&mask);
+    wire.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+    wire
+}
+
+fn text(payload: &str) -> Vec<u8> {
+    frame(FIN | TEXT, payload.as_bytes())
+}
+
+fn close_payload(code: u16, reason: &[u8]) -> Vec<u8> {
+    [&code.to_be_bytes(), reason].concat()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expect {
+    /// A whole message echoed back, possibly in fragments.
+    Message(Opcode, Vec<u8>),
+    Pong(Vec<u8>),
+    /// A Close frame with this code (`None` for an empty payload), after which the server hangs up.
+    Close(Option<u16>),
+}
+
+fn echo_text(payload: &str) -> Expect {
+    Expect::Message(Opcode::Text, payload.as_bytes().to_vec())
+}
+
+fn failed(code: u16) -> Expect {
+    Expect::Close(Some(code))
+}
+
+struct Case {
+    id: &'static str,
+    title: &'static str,
+    send: Vec<Vec<u8>>,
+    /// Unless this ends with a Close, the harness closes the connection normally after the case.
+    expect: Vec<Expect>,
+}
+
+fn case(
+    id: &'static str,
+    title: &'static str,
+    send: impl IntoIterator<Item = Vec<u8>>,
+    expect: impl IntoIterator<Item = Expect>,
+) -> Case {
+    Case {
+        id,
+        title,
+        send: send.into_iter().collect(),
+        expect: expect.into_iter().collect(),
+    }
+}
+
+struct Echo {
+    stream: Mutex<DuplexStream>,
+    state: RwLock<WebSocketState>,
+}
+
+impl WebSocket for Echo {
+    type Stream = DuplexStream;
+
+    async fn state(&self) -> impl Deref<Target = WebSocketState> {
+        self.state.read().await
+    }
+
+    async fn state_mut(&self) -> impl DerefMut<Target = WebSocketState> {
+        self.state.write().await
+    }
+
+    async fn stream_mut(&self) -> impl DerefMut<Target = Self::Stream> {
+        self.stream.lock().await
+    }
+
+    async fn on_message(&mut self, message: Message) -> Result<()> {
+        self.send_message(message).await
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Server {
+    Run,
+    Split,
+}
+
+async fn serve(server: Server, stream: DuplexStream, state: WebSocketState) {
+    match server {
+        Server::Run => {
+            let mut echo = Echo {
+                stream: Mutex::new(stream),
+                state: RwLock::new(state),
+            };
+            let _ = echo.run().await;
+        }
+        Server::Split => {
+            let (sender, mut receiver) = split(stream, state);
+            while let Some(Ok(message)) = receiver.next().await {
+                if sender.send(message).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn next_frame(reader: &mut ReadHalf<DuplexStream>) -> Result<Frame, String> {
+    match timeout(PATIENCE, Frame::read(reader)).await {
+        Ok(Ok(frame)) if frame.header.mask.is_some() => Err("the server masked a frame".to_owned()),
+        Ok(Ok(frame)) => Ok(frame),
+        Ok(Err(error)) => Err(format!("connection dropped: {error}")),
+        Err(_) => Err("timed out".to_owned()),
+    }
+}
+
+/// Checks the next message against `expect`, inflating it with `inflate` if it is compressed.
+async fn check(reader: &mut ReadHalf<DuplexStream>, expect: &Expect, inflate: &mut Deflate) -> Result<(), String> {
+    let frame = next_frame(reader).await?;
+    let received = match frame.header.opcode {
+        Opcode::Text | Opcode::Binary => {
+            let opcode = frame.header.opcode;
+            let compressed = frame.header.rsv1;
+            let mut data = frame.payload;
+            let mut fin = frame.header.fin;
+            while !fin {
+                let next = next_frame(reader).await?;
+                if next.header.opcode != Opcode::Continuation {
+                    return Err(format!("expected a continuation, got {:?}", next.header.opcode));
+                }
+                data.extend(next.payload);
+                fin = next.header.fin;
+            }
+            if compressed {
+                data = inflate
+                    .decompress(&data, usize::MAX)
+                    .map_err(|error| format!("could not inflate the message: {error}"))?;
+            }
+            Expect::Message(opcode, data)
+        }
+        Opcode::Pong => Expect::Pong(frame.payload),
+        Opcode::Close => Expect::Close(frame.payload.first_chunk().map(|code| u16::from_be_bytes(*code))),
+        opcode => return Err(format!("unexpected {opcode:?} frame")),
+    };
+    if received != *expect {
+        return Err(format!("expected {}, got {}", describe(expect), describe(&received)));
+    }
+    if let Expect::Close(_) = received {
+        match timeout(PATIENCE, Frame::read(reader)).await {
+            Ok(Err(Error::IO(_))) => {}
+            Ok(Ok(frame)) => return Err(format!("a {:?} frame followed the Close", frame.header.opcode)),
+            Ok(Err(error)) => return Err(error.to_string()),
+            Err(_) => return Err("the server did not hang up after closing".to_owned()),
+        }
+    }
+    Ok(())
+}
+
+fn describe(expect: &Expect) -> String {
+    match expect {
+        Expect::Message(opcode, data) if data.len() > 32 => format!("{opcode:?} of {} bytes", data.len()),
+        Expect::Message(opcode, data) => format!("{opcode:?} {:?}", String::from_utf8_lossy(data)),
+        Expect::Pong(data) => format!("Pong {data:?}"),
+        Expect::Close(Some(code)) => format!("Close {code}"),
+        Expect::Close(None) => "empty Close".to_owned(),
+    }
+}
+
+async fn run_case(case: &Case, server: Server, chunk: Option<usize>, state: WebSocketState) -> Result<(), String> {
+    let (client, server_stream) = tokio::io::duplex(1 << 16);
+    let server_task = tokio::spawn(serve(server, server_stream, state));
+    let (mut reader, mut writer) = tokio::io::split(client);
+
+    let mut wire = case.send.concat();
+    let mut expect = case.expect.clone();
+    if !matches!(expect.last(), Some(Expect::Close(_))) {
+        wire.extend(frame(FIN | CLOSE, &close_payload(1000, b"")));
+        expect.push(Expect::Close(Some(1000)));
+    }
+    // written concurrently, since the server may answer before it read everything
+    let writer_task = tokio::spawn(async move {
+        for piece in wire.chunks(chunk.unwrap_or(usize::MAX)) {
+            if writer.write_all(piece).await.is_err() {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        // keep the write half open, so the server has to fail on what it got rather than on the end of input
+        writer
+    });
+
+    // only used if the case negotiated permessage-deflate
+    let mut inflate = Deflate::client(&DeflateParams::default(), Compression::default());
+    let result = async {
+        for expect in &expect {
+            check(&mut reader, expect, &mut inflate).await?;
+        }
+        Ok(())
+    }
+    .await;
+    server_task.abort();
+    writer_task.abort();
+    result
+}
+
+/// Runs every case against every server and delivery, printing a report line for each, and fails listing the cases
+/// which did not pass.
+async fn run_cases(cases: &[Case], state: impl Fn() -> WebSocketState) {
+    let mut failures = Vec::new();
+    for case in cases {
+        for server in [Server::Run, Server::Split] {
+            for chunk in [None, Some(7)] {
+                let delivery = chunk.map_or("whole".to_owned(), |chunk| format!("{chunk}-byte chunks"));
+                let label = format!("{} {} [{server:?}, {delivery}]", case.id, case.title);
+                match run_case(case, server, chunk, state()).await {
+                    Ok(()) => println!("pass  {label}"),
+                    Err(reason) => {
+                        println!("FAIL  {label}: {reason}");
+                        failures.push(format!("{label}: {reason}"));
+                    }
+                }
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} failing cases:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+#[tokio::test]
+async fn framing() {
+    let mut cases = Vec::new();
+    for (i, len) in [0, 125, 126, 127, 128, 0xffff, 0x1_0000].into_iter().enumerate() {
+        let ids = ["1.1.1", "1.1.2", "1.1.3", "1.1.4", "1.1.5", "1.1.6", "1.1.7"];
+        let payload = "*".repeat(len);
+        cases.push(case(ids[i], "text message", [text(&payload)], [echo_text(&payload)]));
+        let ids = ["1.2.1", "1.2.2", "1.2.3", "1.2.4", "1.2.5", "1.2.6", "1.2.7"];
+        let payload = vec![0xfe; len];
+        cases.push(case(
+            ids[i],
+            "binary message",
+            [frame(FIN | BINARY, &payload)],
+            [Expect::Message(Opcode::Binary, payload)],
+        ));
+    }
+    cases.push(case(
+        "1.3.1",
+        "unmasked client frame",
+        [vec![FIN | TEXT, 5, b'H', b'e', b'l', b'l', b'o']],
+        [failed(1002)],
+    ));
+    cases.push(case(
+        "1.3.2",
+        "several messages in one write",
+        [text("one"), text("two"), text("three")],
+        [echo_text("one"), echo_text("two"), echo_text("three")],
+    ));
+    run_cases(&cases, WebSocketState::default).await;
+}
+
+#[tokio::test]
+async fn pings() {
+    let cases = [
+        case(
+            "2.1",
+            "ping without payload",
+            [frame(FIN | PING, b"")],
+            [Expect::Pong(Vec::new())],
+        ),
+        case(
+            "2.2",
+            "ping with text payload",
+            [frame(FIN | PING, b"Hello, world!")],
+            [Expect::Pong(b"Hello, world!".to_vec())],
+        ),
+        case(
+            "2.3",
+            "ping with binary payload",
+            [frame(FIN | PING, &[0x00, 0xff, 0xfe, 0xfd])],
+            [Expect::Pong(vec![0x00, 0xff, 0xfe, 0xfd])],
+        ),
+        case(
+            "2.4",
+            "ping with 125 bytes of payload",
+            [frame(FIN | PING, &[0xfe; 125])],
+            [Expect::Pong(vec![0xfe; 125])],
+        ),
+        case(
+            "2.5",
+            "ping with 126 bytes of payload",
+            [frame(FIN | PING, &[0xfe; 126])],
+            [failed(1002)],
+        ),
+        case(
+            "2.7",
+            "unsolicited pong is ignored",
+            [frame(FIN | PONG, b""), text("after")],
+            [echo_text("after")],
+        ),
+        case(
+            "2.8",
+            "unsolicited pong with payload is ignored",
+            [frame(FIN | PONG, b"unsolicited"), text("after")],
+            [echo_text("after")],
+        ),
+        case(
+            "2.10",
+            "ten pings answered in order",
+            (0..10).map(|i| frame(FIN | PING, format!("ping {i}").as_bytes())),
+            (0..10).map(|i| Expect::Pong(format!("ping {i}").into_bytes())),
+        ),
+    ];
+    run_cases(&cases, WebSocketState::default).await;
+}
+
+#[tokio::test]
+async fn reserved_bits() {
+    let cases = [
+        case(
+            "3.1",
+            "RSV1 on a text frame",
+            [frame(FIN | RSV1 | TEXT, b"Hello")],
+            [failed(1002)],
+        ),
+        case(
+            "3.2",
+            "RSV2 on a text frame after a valid message",
+            [text("Hello"), frame(FIN | RSV2 | TEXT, b"Hello")],
+            [echo_text("Hello"), failed(1002)],
+        ),
+        case(
+            "3.3",
+            "RSV1 and RSV2 on a text frame after a valid message",
+            [text("Hello"), frame(FIN | RSV1 | RSV2 | TEXT, b"Hello")],
+            [echo_text("Hello"), failed(1002)],
+        ),
+        case(
+            "3.4",
+            "RSV3 on a text frame",
+            [frame(FIN | RSV3 | TEXT, b"Hello")],
+            [failed(1002)],
+        ),
+        case(
+            "3.5",
+            "RSV1 and RSV3 on a binary frame",
+            [frame(FIN | RSV1 | RSV3 | BINARY, &[0xff; 8])],
+            [failed(1002)],
+        ),
+        case(
+            "3.6",
+            "RSV2 and RSV3 on a ping",
+            [frame(FIN | RSV2 | RSV3 | PING, b"Hello")],
+            [failed(1002)],
+        ),
+        case(
+            "3.7",
+            "every RSV bit on a close",
+            [frame(FIN | RSV1 | RSV2 | RSV3 | CLOSE, &close_payload(1000, b""))],
+            [failed(1002)],
+        ),
+    ];
+    run_cases(&cases, WebSocketState::default).await;
+}
+
+#[tokio::test]
+async fn opcodes() {
+    let mut cases = Vec::new();
+    let ids = ["4.1.1", "4.1.2", "4.1.3", "4.1.4", "4.1.5"];
+    for (id, opcode) in ids.into_iter().zip(3..=7) {
+        cases.push(case(
+            id,
+            "reserved non-control opcode",
+            [text("Hello"), frame(FIN | opcode, b"reserved")],
+            [echo_text("Hello"), failed(1002)],
+        ));
+    }
+    let ids = ["4.2.1", "4.2.2", "4.2.3", "4.2.4", "4.2.5"];
+    for (id, opcode) in ids.into_iter().zip(0xb..=0xf) {
+        cases.push(case(
+            id,
+            "reserved control opcode",
+            [text("Hello"), frame(FIN | opcode, b"reserved")],
+            [echo_text("Hello"), failed(1002)],
+        ));
+    }
+    run_cases(&cases, WebSocketState::default).await;
+}
+
+#[tokio::test]
+async fn fragmentation() {
+    let cases = [
+        case(
+            "5.1",
+            "fragmented ping",
+            [frame(PING, b"frag"), frame(FIN | CONTINUATION, b"ment")],
+            [failed(1002)],
+        ),
+        case(
+            "5.2",
+            "fragmented pong",
+            [frame(PONG, b"frag"), frame(FIN | CONTINUATION, b"ment")],
+            [failed(1002)],
+        ),
+        case(
+            "5.3",
+            "text in two fragments",
+            [frame(TEXT, b"frag"), frame(FIN | CONTINUATION, b"ment")],
+            [echo_text("fragment")],
+        ),
+        case(
+            "5.4",
+            "binary in many fragments",
+            [
+                frame(BINARY, &[1]),
+                frame(CONTINUATION, &[2]),
+                frame(CONTINUATION, &[]),
+                frame(FIN | CONTINUATION, &[3]),
+            ],
+            [Expect::Message(Opcode::Binary, vec![1, 2, 3])],
+        ),
+        case(
+            "5.6",
+            "ping between fragments",
+            [
+                frame(TEXT, b"frag"),
+                frame(FIN | PING, b"ping"),
+                frame(FIN | CONTINUATION, b"ment"),
+            ],
+            [Expect::Pong(b"ping".to_vec()), echo_text("fragment")],
+        ),
+        case(
+            "5.8",
+            "pings between every fragment",
+            [
+                frame(TEXT, b"f"),
+                frame(FIN | PING, b"1"),
+                frame(CONTINUATION, b"r"),
+                frame(FIN | PING, b"2"),
+                frame(FIN | CONTINUATION, b"ag"),
+            ],
+            [
+                Expect::Pong(b"1".to_vec()),
+                Expect::Pong(b"2".to_vec()),
+                echo_text("frag"),
+            ],
+        ),
+        case(
+            "5.9",
+            "continuation without a message",
+            [frame(FIN | CONTINUATION, b"orphan"), text("Hello")],
+            [failed(1002)],
+        ),
+        case(
+            "5.10",
+            "unfinished continuation without a message",
+            [frame(CONTINUATION, b"orphan"), text("Hello")],
+            [failed(1002)],
+        ),
+        case(
+            "5.18",
+            "new message before the fragmented one finished",
+            [frame(TEXT, b"frag"), frame(FIN | TEXT, b"ment")],
+            [failed(1002)],
+        ),
+        case(
+            "5.19",
+            "fragmented messages back to back",
+            [
+                frame(TEXT, b"one "),
+                frame(FIN | CONTINUATION, b"two"),
+                frame(BINARY, &[3]),
+                frame(FIN | CONTINUATION, &[4]),
+            ],
+            [echo_text("one two"), Expect::Message(Opcode::Binary, vec![3, 4])],
+        ),
+    ];
+    run_cases(&cases, WebSocketState::default).await;
+}
+
+#[tokio::test]
+async fn utf8() {
+    let kosme = "κόσμε".as_bytes();
+    // "κόσμε" followed by a UTF-16 surrogate and "edited", from the Autobahn 6.x cases
+    let surrogate = [kosme, &[0xed, 0xa0, 0x80], b"edited"].concat();
+    let cases = [
+        case("6.1.1", "empty text message", [text("")], [echo_text("")]),
+        case("6.2.1", "multi-byte text", [text("κόσμε")], [echo_text("κόσμε")]),
+        case(
+            "6.2.3",
+            "text split inside a character",
+            [frame(TEXT, &kosme[..3]), frame(FIN | CONTINUATION, &kosme[3..])],
+            [echo_text("κόσμε")],
+        ),
+        case(
+            "6.2.4",
+            "text split byte by byte",
+            kosme.iter().enumerate().map(|(i, &byte)| {
+                let first = match i {
+                    0 => TEXT,
+                    i if i == kosme.len() - 1 => FIN | CONTINUATION,
+                    _ => CONTINUATION,
+                };
+                frame(first, &[byte])
+            }),
+            [echo_text("κόσμε")],
+        ),
+        case(
+            "6.3.1",
+            "surrogate in text",
+            [frame(FIN | TEXT, &surrogate)],
+            [failed(1007)],
+        ),
+        case(
+            "6.4.1",
+            "invalid text fails before the message ends",
+            [frame(TEXT, &kosme[..2]), frame(CONTINUATION, &[0xf4, 0x90, 0x80, 0x80])],
+            [failed(1007)],
+        ),
+        case(
+            "6.4.3",
+            "invalid first fragment fails before the message ends",
+            [frame(
+                TEXT,
+                &[
+                    0xce, 0xba, 0xe1, 0xbd, 0xb9, 0xcf, 0x83, 0xce, 0xbc, 0xce, 0xb5, 0xf4, 0x90,
+                ],
+            )],
+            [failed(1007)],
+        ),
+        case(
+            "6.5.1",
+            "overlong encoding",
+            [frame(FIN | TEXT, &[0xc0, 0xaf])],
+            [failed(1007)],
+        ),
+        case(
+            "6.6.1",
+            "lone continuation byte",
+            [frame(FIN | TEXT, &[0x80])],
+            [failed(1007)],
+        ),
+        case(
+            "6.7.1",
+            "truncated character at the end",
+            [frame(FIN | TEXT, &kosme[..kosme.len() - 1])],
+            [failed(1007)],
+        ),
+        case(
+            "6.8.1",
+            "largest code point",
+            [text("\u{10ffff}")],
+            [echo_text("\u{10ffff}")],
+        ),
+        case(
+            "6.8.2",
+            "code point past the largest",
+            [frame(FIN | TEXT, &[0xf4, 0x90, 0x80, 0x80])],
+            [failed(1007)],
+        ),
+        case(
+            "6.9.1",
+            "invalid bytes in a binary message are fine",
+            [frame(FIN | BINARY, &[0xc0, 0xaf, 0xff])],
+            [Expect::Message(Opcode::Binary, vec![0xc0, 0xaf, 0xff])],
+        ),
+    ];
+    run_cases(&cases, WebSocketState::default).await;
+}
+
+#[tokio::test]
+async fn close_handling() {
+    let mut cases = vec![
+        case(
+            "7.1.1",
+            "close after a message",
+            [text("Hello"), frame(FIN | CLOSE, &close_payload(1000, b""))],
+            [echo_text("Hello"), Expect::Close(Some(1000))],
+        ),
+        case(
+            "7.1.2",
+            "nothing is answered after close",
+            [
+                frame(FIN | CLOSE, &close_payload(1000, b"")),
+                frame(FIN | PING, b"late"),
+                text("late"),
+            ],
+            [Expect::Close(Some(1000))],
+        ),
+        case(
+            "7.1.5",
+            "close in the middle of a fragmented message",
+            [frame(TEXT, b"frag"), frame(FIN | CLOSE, &close_payload(1000, b""))],
+            [Expect::Close(Some(1000))],
+        ),
+        case(
+            "7.3.1",
+            "close without payload",
+            [frame(FIN | CLOSE, b"")],
+            [Expect::Close(None)],
+        ),
+        case(
+            "7.3.2",
+            "close with a 1-byte payload",
+            [frame(FIN | CLOSE, &[0x03])],
+            [failed(1002)],
+        ),
+        case(
+            "7.3.3",
+            "close with a reason",
+            [frame(FIN | CLOSE, &close_payload(1000, b"Hello World!"))],
+            [Expect::Close(Some(1000))],
+        ),
+        case(
+            "7.3.4",
+            "close with a 123-byte reason",
+            [frame(FIN | CLOSE, &close_payload(1000, &[b'*'; 123]))],
+            [Expect::Close(Some(1000))],
+        ),
+        case(
+            "7.3.6",
+            "close with a 124-byte reason",
+            [frame(FIN | CLOSE, &close_payload(1000, &[b'*'; 124]))],
+            [failed(1002)],
+        ),
+        case(
+            "7.5.1",
+            "close with an invalid UTF-8 reason",
+            [frame(
+                FIN | CLOSE,
+                &close_payload(1000, &[0xce, 0xba, 0xed, 0xa0, 0x80]),
+            )],
+            [failed(1007)],
+        ),
+    ];
+    let valid = [
+        ("7.7.1", 1000),
+        ("7.7.2", 1001),
+        ("7.7.3", 1002),
+        ("7.7.4", 1003),
+        ("7.7.5", 1007),
+        ("7.7.6", 1008),
+        ("7.7.7", 1009),
+        ("7.7.8", 1010),
+        ("7.7.9", 1011),
+        ("7.7.10", 3000),
+        ("7.7.11", 3999),
+        ("7.7.12", 4000),
+        ("7.7.13", 4999),
+    ];
+    for (id, code) in valid {
+        cases.push(case(
+            id,
+            "valid close code is echoed",
+            [frame(FIN | CLOSE, &close_payload(code, b""))],
+            [Expect::Close(Some(code))],
+        ));
+    }
+    let invalid = [
+        ("7.9.1", 0),
+        ("7.9.2", 999),
+        ("7.9.3", 1004),
+        ("7.9.4", 1005),
+        ("7.9.5", 1006),
+        ("7.9.6", 1016),
+        ("7.9.7", 1100),
+        ("7.9.8", 2000),
+        ("7.9.9", 2999),
+        ("7.13.1", 5000),
+        ("7.13.2", 65535),
+    ];
+    for (id, code) in invalid {
+        cases.push(case(
+            id,
+            "invalid close code",
+            [frame(FIN | CLOSE, &close_payload(code, b""))],
+            [failed(1002)],
+        ));
+    }
+    run_cases(&cases, WebSocketState::default).await;
+}
+
+#[tokio::test]
+async fn limits() {
+    const LIMIT: usize = 0x1_0000;
+    let fragments = |len: usize, size: usize| {
+        let chunks = vec![b'*'; len].chunks(size).map(<[u8]>::to_vec).collect::<Vec<_>>();
+        let last = chunks.len() - 1;
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let opcode = if i == 0 { TEXT } else { CONTINUATION };
+                frame(if i == last { FIN | opcode } else { opcode }, &chunk)
+            })
+            .collect::<Vec<_>>()
+    };
+    let cases = [
+        case(
+            "9.1.1",
+            "message at the size limit",
+            [frame(FIN | BINARY, &vec![0xab; LIMIT])],
+            [Expect::Message(Opcode::Binary, vec![0xab; LIMIT])],
+        ),
+        case(
+            "9.1.2",
+            "message past the size limit",
+            [frame(FIN | BINARY, &vec![0xab; LIMIT + 1])],
+            [failed(1009)],
+        ),
+        case(
+            "9.2.1",
+            "fragmented message at the size limit",
+            fragments(LIMIT, 1000),
+            [Expect::Message(Opcode::Text, vec![b'*'; LIMIT])],
+        ),
+        case(
+            "9.2.2",
+            "fragmented message growing past the size limit",
+            fragments(LIMIT + 1, 1000),
+            [failed(1009)],
+        ),
+        case(
+            "9.3.1",
+            "oversized frame announced in the header only",
+            [[
+                [FIN | BINARY, 0x80 | 127].as_slice(),
+                &0x7fff_ffff_u64.to_be_bytes(),
+                &[1, 2, 3, 4],
+            ]
+            .concat()],
+            [failed(1009)],
+        ),
+        case(
+            "9.4.1",
+            "small frames interleaved with pings",
+            [
+                frame(TEXT, b"a"),
+                frame(FIN | PING, b"p"),
+                frame(FIN | CONTINUATION, b"b"),
+            ],
+            [Expect::Pong(b"p".to_vec()), echo_text("ab")],
+        ),
+    ];
+    run_cases(&cases, || WebSocketState::default().with_max_message_size(LIMIT)).await;
+}
+
+#[tokio::test]
+async fn permessage_deflate() {
+    // one client context for the whole case, as its messages are sent in order over one connection
+    let deflate = || Deflate::client(&DeflateParams::default(), Compression::default());
+    let compressed =
+        |deflate: &mut Deflate, first: u8, payload: &[u8]| frame(first | RSV1, &deflate.compress(payload).unwrap());
+    let long = "Hello, compressed world! ".repeat(40);
+
+    let mut context = deflate();
+    let takeover = [
+        compressed(&mut context, FIN | TEXT, long.as_bytes()),
+        compressed(&mut context, FIN | TEXT, long.as_bytes()),
+    ];
+    let fragmented = deflate().compress(long.as_bytes()).unwrap();
+    let (head, tail) = fragmented.split_at(fragmented.len() / 2);
+    let cases = [
+        case(
+            "12.1.1",
+            "short compressed text, echoed uncompressed",
+            [compressed(&mut deflate(), FIN | TEXT, b"Hello")],
+            [echo_text("Hello")],
+        ),
+        case(
+            "12.1.2",
+            "long compressed text, echoed compressed",
+            [compressed(&mut deflate(), FIN | TEXT, long.as_bytes())],
+            [echo_text(&long)],
+        ),
+        case(
+            "12.1.3",
+            "compressed binary",
+            [compressed(&mut deflate(), FIN | BINARY, &[0xab; 1000])],
+            [Expect::Message(Opcode::Binary, vec![0xab; 1000])],
+        ),
+        case(
+            "12.1.4",
+            "compressed empty message",
+            [compressed(&mut deflate(), FIN | TEXT, b"")],
+            [echo_text("")],
+        ),
+        case(
+            "12.2.1",
+            "messages referring back to the previous one",
+            takeover,
+            [echo_text(&long), echo_text(&long)],
+        ),
+        case(
+            "12.2.2",
+            "compressed and uncompressed messages mixed",
+            [
+                text("plain"),
+                compressed(&mut deflate(), FIN | TEXT, long.as_bytes()),
+                text("plain again"),
+            ],
+            [echo_text("plain"), echo_text(&long), echo_text("plain again")],
+        ),
+        case(
+            "12.3.1",
+            "compressed message in fragments, RSV1 on the first only",
+            [frame(RSV1 | TEXT, head), frame(FIN | CONTINUATION, tail)],
+            [echo_text(&long)],
+        ),
+        case(
+            "12.3.2",
+            "RSV1 on a continuation frame",
+            [frame(RSV1 | TEXT, head), frame(FIN | RSV1 | CONTINUATION, tail)],
+            [failed(1002)],
+        ),
+        case(
+            "12.3.3",
+            "RSV1 on a ping",
+            [frame(FIN | RSV1 | PING, b"Hello")],
+            [failed(1002)],
+        ),
+        case(
+            "12.4.1",
+            "corrupt compressed data",
+            [frame(FIN | RSV1 | BINARY, &[0xff; 8])],
+            [failed(1007)],
+        ),
+        case(
+            "12.4.2",
+            "message inflating past the size limit",
+            [compressed(&mut deflate(), FIN | BINARY, &[0; 0x1_0001])],
+            [failed(1009)],
+        ),
+    ];
+    let handshake =
+        Response::new(101, "Switching Protocols").with_header("Sec-WebSocket-Extensions", "permessage-deflate");
+    run_cases(&cases, || {
+        WebSocketState::default()
+            .with_max_message_size(0x1_0000)
+            .with_handshake(&handshake)
+    })
+    .await;
+}
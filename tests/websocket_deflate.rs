@@ -0,0 +1,207 @@
+use cliud::websocket::{Deflate, DeflateConfig, DeflateParams, Error};
+use flate2::Compression;
+
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+fn offers(header: &str) -> Vec<DeflateParams> {
+    DeflateParams::parse_offers(header).collect()
+}
+
+#[test]
+fn offers_are_parsed() {
+    assert_eq!(offers("permessage-deflate"), [DeflateParams::default()]);
+    assert_eq!(
+        offers("permessage-deflate; client_max_window_bits; server_no_context_takeover"),
+        [DeflateParams {
+            server_no_context_takeover: true,
+            client_max_window_bits: Some(15),
+            ..DeflateParams::default()
+        }]
+    );
+    assert_eq!(
+        offers(r#"permessage-deflate;server_max_window_bits="10";client_no_context_takeover"#),
+        [DeflateParams {
+            client_no_context_takeover: true,
+            server_max_window_bits: Some(10),
+            ..DeflateParams::default()
+        }]
+    );
+}
+
+#[test]
+fn invalid_offers_are_skipped() {
+    for header in [
+        "x-webkit-deflate-frame",
+        "permessage-deflate; unknown",
+        "permessage-deflate; server_no_context_takeover; server_no_context_takeover",
+        "permessage-deflate; server_no_context_takeover=1",
+        "permessage-deflate; server_max_window_bits",
+        "permessage-deflate; server_max_window_bits=7",
+        "permessage-deflate; client_max_window_bits=16",
+        "permessage-deflate; client_max_window_bits=abc",
+    ] {
+        assert_eq!(offers(header), [], "{header}");
+    }
+    assert_eq!(
+        offers("permessage-deflate; bogus, permessage-deflate; client_no_context_takeover"),
+        [DeflateParams {
+            client_no_context_takeover: true,
+            ..DeflateParams::default()
+        }]
+    );
+}
+
+#[test]
+fn headers_round_trip() {
+    let params = DeflateParams {
+        server_no_context_takeover: true,
+        client_no_context_takeover: true,
+        server_max_window_bits: Some(15),
+        client_max_window_bits: Some(9),
+    };
+    assert_eq!(
+        params.to_header(),
+        "permessage-deflate; server_no_context_takeover; client_no_context_takeover; server_max_window_bits=15; \
+         client_max_window_bits=9"
+    );
+    assert_eq!(offers(&params.to_header()), [params]);
+    assert_eq!(DeflateParams::default().to_header(), "permessage-deflate");
+}
+
+#[test]
+fn negotiation_honours_the_offer_and_the_config() {
+    let config = DeflateConfig::default();
+    assert_eq!(config.negotiate("permessage-deflate"), Some(DeflateParams::default()));
+    assert_eq!(config.negotiate("x-unknown"), None);
+
+    // a smaller server window cannot be honoured, so the next offer is taken
+    assert_eq!(
+        config
+            .negotiate("permessage-deflate; server_max_window_bits=10, permessage-deflate; server_no_context_takeover"),
+        Some(DeflateParams {
+            server_no_context_takeover: true,
+            ..DeflateParams::default()
+        })
+    );
+    assert_eq!(config.negotiate("permessage-deflate; server_max_window_bits=10"), None);
+    assert_eq!(
+        config.negotiate("permessage-deflate; server_max_window_bits=15"),
+        Some(DeflateParams {
+            server_max_window_bits: Some(15),
+            ..DeflateParams::default()
+        })
+    );
+
+    let config = DeflateConfig {
+        server_no_context_takeover: true,
+        client_no_context_takeover: true,
+        client_max_window_bits: Some(10),
+    };
+    assert_eq!(
+        config.negotiate("permessage-deflate"),
+        Some(DeflateParams {
+            server_no_context_takeover: true,
+            client_no_context_takeover: true,
+            ..DeflateParams::default()
+        })
+    );
+    // the window is only limited if the client announced it can be
+    assert_eq!(
+        config.negotiate("permessage-deflate; client_max_window_bits=12"),
+        Some(DeflateParams {
+            server_no_context_takeover: true,
+            client_no_context_takeover: true,
+            client_max_window_bits: Some(10),
+            ..DeflateParams::default()
+        })
+    );
+    assert_eq!(
+        config
+            .negotiate("permessage-deflate; client_max_window_bits=9")
+            .and_then(|params| params.client_max_window_bits),
+        Some(9)
+    );
+}
+
+const MESSAGES: [&[u8]; 4] = [
+    b"",
+    b"Hello, Hello, Hello, Hello, Hello!",
+    b"Hello, Hello, Hello, Hello, Hello!",
+    &[0xab; 70_000],
+];
+
+/// Sends `MESSAGES` from server to client and back, returning the compressed sizes of each direction.
+fn round_trip(params: &DeflateParams) -> (Vec<usize>, Vec<usize>) {
+    let mut server = Deflate::server(params, Compression::default());
+    let mut client = Deflate::client(params, Compression::default());
+    let mut sizes = (Vec::new(), Vec::new());
+    for message in MESSAGES {
+        let compressed = server.compress(message).unwrap();
+        assert!(!compressed.ends_with(&TAIL));
+        assert_eq!(client.decompress(&compressed, usize::MAX).unwrap(), message);
+        sizes.0.push(compressed.len());
+
+        let compressed = client.compress(message).unwrap();
+        assert!(!compressed.ends_with(&TAIL));
+        assert_eq!(server.decompress(&compressed, usize::MAX).unwrap(), message);
+        sizes.1.push(compressed.len());
+    }
+    sizes
+}
+
+#[test]
+fn messages_round_trip_with_context_takeover() {
+    let (server, client) = round_trip(&DeflateParams::default());
+    // the repeated message refers back to the previous one
+    assert!(server[2] < server[1], "{server:?}");
+    assert!(client[2] < client[1], "{client:?}");
+}
+
+#[test]
+fn messages_round_trip_without_context_takeover() {
+    let (server, client) = round_trip(&DeflateParams {
+        server_no_context_takeover: true,
+        client_no_context_takeover: true,
+        ..DeflateParams::default()
+    });
+    assert_eq!(server[2], server[1]);
+    assert_eq!(client[2], client[1]);
+
+    let (server, client) = round_trip(&DeflateParams {
+        server_no_context_takeover: true,
+        ..DeflateParams::default()
+    });
+    assert_eq!(server[2], server[1]);
+    assert!(client[2] < client[1], "{client:?}");
+}
+
+#[test]
+fn inflating_past_the_limit_fails() {
+    let params = DeflateParams::default();
+    let mut server = Deflate::server(&params, Compression::default());
+    let mut client = Deflate::client(&params, Compression::default());
+    let bomb = client.compress(&[0; 1 << 20]).unwrap();
+    assert!(bomb.len() < 1 << 12);
+
+    assert_eq!(server.decompress(&bomb, 1 << 20).unwrap().len(), 1 << 20);
+    assert!(matches!(
+        server.decompress(&bomb, (1 << 20) - 1),
+        Err(Error::MessageTooBig)
+    ));
+
+    let mut server = Deflate::server(&params, Compression::default());
+    assert!(matches!(server.decompress(&bomb, 1000), Err(Error::MessageTooBig)));
+    // the context was reset, so a message compressed from scratch still inflates
+    let mut fresh = Deflate::client(&params, Compression::default());
+    let hello = fresh.compress(b"hello").unwrap();
+    assert_eq!(server.decompress(&hello, 1000).unwrap(), b"hello");
+}
+
+#[test]
+fn corrupt_data_is_rejected() {
+    let mut server = Deflate::server(&DeflateParams::default(), Compression::default());
+    assert!(matches!(
+        server.decompress(&[0xff, 0xff, 0xff, 0xff], 1000),
+        Err(Error::InvalidCompressedData)
+    ));
+}
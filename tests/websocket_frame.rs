@@ -0,0 +1,221 @@
+use cliud::websocket::{Error, Frame, FrameHeader, Opcode, apply_mask};
+
+const OPCODES: [Opcode; 6] = [
+    Opcode::Continuation,
+    Opcode::Text,
+    Opcode::Binary,
+    Opcode::Close,
+    Opcode::Ping,
+    Opcode::Pong,
+];
+
+/// Every length around a change of the length encoding.
+const BOUNDARIES: [u64; 11] = [
+    0,
+    1,
+    124,
+    125,
+    126,
+    127,
+    0xfffe,
+    0xffff,
+    0x1_0000,
+    0x1_0001,
+    0x7fff_ffff_ffff_ffff,
+];
+
+/// A tiny xorshift generator, so the round trips cover arbitrary inputs without extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next().to_le_bytes()[0]).collect()
+    }
+
+    fn mask(&mut self) -> Option<[u8; 4]> {
+        let value = self.next();
+        (value & 1 == 0).then(|| value.to_le_bytes()[1..5].try_into().unwrap())
+    }
+}
+
+#[test]
+fn header_round_trips_at_every_length_boundary() {
+    let mut rng = Rng(0x5eed);
+    for payload_len in BOUNDARIES {
+        for opcode in OPCODES {
+            for bits in 0..16_u8 {
+                let header = FrameHeader {
+                    fin: bits & 1 != 0,
+                    rsv1: bits & 2 != 0,
+                    rsv2: bits & 4 != 0,
+                    rsv3: bits & 8 != 0,
+                    opcode,
+                    mask: rng.mask(),
+                    payload_len,
+                };
+                let mut buf = Vec::new();
+                header.encode(&mut buf);
+                assert_eq!(buf.len(), header.encoded_len());
+                assert_eq!(FrameHeader::decode(&buf).unwrap(), Some((header, buf.len())));
+            }
+        }
+    }
+}
+
+#[test]
+fn header_uses_the_shortest_length_encoding() {
+    let cases: [(u64, &[u8]); 7] = [
+        (0, &[0x82, 0x00]),
+        (125, &[0x82, 0x7d]),
+        (126, &[0x82, 0x7e, 0x00, 0x7e]),
+        (127, &[0x82, 0x7e, 0x00, 0x7f]),
+        (0xffff, &[0x82, 0x7e, 0xff, 0xff]),
+        (0x1_0000, &[0x82, 0x7f, 0, 0, 0, 0, 0, 0x01, 0x00, 0x00]),
+        (
+            0x7fff_ffff_ffff_ffff,
+            &[0x82, 0x7f, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+        ),
+    ];
+    for (payload_len, expected) in cases {
+        let mut buf = Vec::new();
+        FrameHeader::new(true, Opcode::Binary, payload_len).encode(&mut buf);
+        assert_eq!(buf, expected, "payload length {payload_len}");
+    }
+}
+
+#[test]
+fn header_encodes_rsv_bits_and_mask() {
+    let mut buf = Vec::new();
+    FrameHeader::new(false, Opcode::Text, 3)
+        .with_rsv1(true)
+        .with_mask([1, 2, 3, 4])
+        .encode(&mut buf);
+    assert_eq!(buf, [0x41, 0x83, 1, 2, 3, 4]);
+}
+
+#[test]
+fn frame_round_trips_around_every_length_boundary() {
+    let mut rng = Rng(0xc0ffee);
+    let lengths = BOUNDARIES.iter().filter(|&&len| len <= 0x1_0001);
+    for &len in lengths {
+        let len = usize::try_from(len).unwrap();
+        for opcode in OPCODES {
+            let mut frame = Frame::new(rng.next() & 1 == 0, opcode, rng.bytes(len));
+            frame.header.mask = rng.mask();
+            let encoded = frame.encode();
+            assert_eq!(encoded.len(), frame.header.encoded_len() + len);
+            assert_eq!(Frame::decode(&encoded).unwrap(), Some((frame, encoded.len())));
+        }
+    }
+}
+
+#[test]
+fn frame_round_trips_arbitrary_frames() {
+    let mut rng = Rng(42);
+    for _ in 0..500 {
+        let len = usize::try_from(rng.next() % 0x2_0000).unwrap();
+        let opcode = OPCODES[usize::try_from(rng.next() % 6).unwrap()];
+        let mut frame = Frame::new(rng.next() & 1 == 0, opcode, rng.bytes(len));
+        frame.header.mask = rng.mask();
+        let encoded = frame.encode();
+        assert_eq!(Frame::decode(&encoded).unwrap(), Some((frame, encoded.len())));
+    }
+}
+
+#[tokio::test]
+async fn frame_round_trips_through_async_io() {
+    let mut rng = Rng(7);
+    let mut frames = Vec::new();
+    let mut wire = Vec::new();
+    for len in [0, 125, 126, 0xffff, 0x1_0000] {
+        let mut frame = Frame::new(true, Opcode::Binary, rng.bytes(len));
+        frame.header.mask = rng.mask();
+        frame.write(&mut wire).await.unwrap();
+        frames.push(frame);
+    }
+    let mut reader = wire.as_slice();
+    for frame in frames {
+        assert_eq!(Frame::read(&mut reader).await.unwrap(), frame);
+    }
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn decode_waits_for_a_whole_frame() {
+    let frame = Frame::new(true, Opcode::Text, vec![b'x'; 300]).with_mask([9, 8, 7, 6]);
+    let encoded = frame.encode();
+    for end in 0..encoded.len() {
+        assert_eq!(Frame::decode(&encoded[..end]).unwrap(), None, "prefix of {end} bytes");
+    }
+    let mut trailing = encoded.clone();
+    trailing.extend_from_slice(b"next frame");
+    assert_eq!(Frame::decode(&trailing).unwrap(), Some((frame, encoded.len())));
+}
+
+#[test]
+fn masked_payload_is_masked_on_the_wire() {
+    let frame = Frame::new(true, Opcode::Text, b"Hello".to_vec()).with_mask([0x37, 0xfa, 0x21, 0x3d]);
+    // the example from RFC 6455 section 5.7
+    assert_eq!(
+        frame.encode(),
+        [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]
+    );
+}
+
+#[test]
+fn mask_can_be_applied_in_pieces() {
+    let mask = [1, 2, 3, 4];
+    let mut whole = b"some payload split across reads".to_vec();
+    let mut pieces = whole.clone();
+    apply_mask(mask, &mut whole, 0);
+    let (head, tail) = pieces.split_at_mut(7);
+    apply_mask(mask, head, 0);
+    apply_mask(mask, tail, 7);
+    assert_eq!(whole, pieces);
+}
+
+#[test]
+fn decode_rejects_invalid_headers() {
+    assert!(matches!(
+        FrameHeader::decode(&[0x83, 0x00]),
+        Err(Error::InvalidOpcode(3))
+    ));
+    assert!(matches!(
+        FrameHeader::decode(&[0x8b, 0x00]),
+        Err(Error::InvalidOpcode(11))
+    ));
+    let too_long = [0x82, 0x7f, 0x80, 0, 0, 0, 0, 0, 0, 0];
+    assert!(matches!(FrameHeader::decode(&too_long), Err(Error::BadProtocol)));
+}
+
+#[tokio::test]
+async fn reading_refuses_long_payloads_before_allocating() {
+    // claims 2^62 bytes, and carries a few
+    let huge = [0x82, 0x7f, 0x40, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3];
+    assert!(matches!(
+        Frame::read(&mut huge.as_slice()).await,
+        Err(Error::MessageTooBig)
+    ));
+    assert!(matches!(
+        Frame::read_with_limit(&mut huge.as_slice(), usize::MAX).await,
+        Err(Error::IO(error)) if error.kind() == std::io::ErrorKind::UnexpectedEof
+    ));
+
+    let wire = Frame::new(true, Opcode::Binary, vec![7; 200]).encode();
+    assert!(matches!(
+        Frame::read_with_limit(&mut wire.as_slice(), 199).await,
+        Err(Error::MessageTooBig)
+    ));
+    let frame = Frame::read_with_limit(&mut wire.as_slice(), 200).await.unwrap();
+    assert_eq!(frame.payload, [7; 200]);
+
+    let short = wire.get(..wire.len() - 1).unwrap();
+    assert!(matches!(Frame::read(&mut &*short).await, Err(Error::IO(_))));
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use cliud::websocket::{Frame, Hub, Message, Opcode, SlowConsumer, WebSocketState, split};
+use tokio::time::timeout;
+
+fn text(text: &str) -> Option<Message> {
+    Some(Message::from(text))
+}
+
+#[tokio::test]
+async fn broadcasts_reach_the_room_members() {
+    let hub = Hub::new();
+    let mut first = hub.register();
+    let mut second = hub.register();
+    let mut third = hub.register();
+    first.join("a");
+    second.join("a");
+    third.join("b");
+    assert_ne!(first.id(), second.id());
+    assert_eq!(hub.members("a"), [first.id(), second.id()]);
+
+    assert_eq!(hub.broadcast("a", "to a"), 2);
+    assert_eq!(hub.broadcast("b", "to b"), 1);
+    assert_eq!(hub.broadcast("c", "to nobody"), 0);
+    assert_eq!(first.recv().await, text("to a"));
+    assert_eq!(second.recv().await, text("to a"));
+    assert_eq!(third.recv().await, text("to b"));
+
+    second.leave("a");
+    third.leave("b");
+    assert_eq!(hub.members("a"), [first.id()]);
+    assert!(hub.members("b").is_empty());
+    assert_eq!(hub.broadcast("a", vec![1, 2, 3]), 1);
+    assert_eq!(first.recv().await, Some(Message::Binary(vec![1, 2, 3])));
+}
+
+#[tokio::test]
+async fn slow_consumers_miss_messages_by_default() {
+    let hub = Hub::new().with_queue_size(1);
+    let mut slow = hub.register();
+    let mut fast = hub.register();
+    slow.join("room");
+    fast.join("room");
+
+    assert_eq!(hub.broadcast("room", "1"), 2);
+    assert_eq!(fast.recv().await, text("1"));
+    assert_eq!(hub.broadcast("room", "2"), 1);
+    assert_eq!(fast.recv().await, text("2"));
+    assert_eq!(hub.members("room"), [slow.id(), fast.id()]);
+
+    assert_eq!(slow.recv().await, text("1"));
+    assert_eq!(hub.broadcast("room", "3"), 2);
+    assert_eq!(slow.recv().await, text("3"));
+}
+
+#[tokio::test]
+async fn slow_consumers_can_be_disconnected() {
+    let hub = Hub::new()
+        .with_queue_size(1)
+        .with_slow_consumer(SlowConsumer::Disconnect);
+    let mut slow = hub.register();
+    let mut fast = hub.register();
+    slow.join("room");
+    slow.join("other");
+    fast.join("room");
+
+    assert_eq!(hub.broadcast("room", "1"), 2);
+    assert_eq!(fast.recv().await, text("1"));
+    assert_eq!(hub.broadcast("room", "2"), 1);
+    assert_eq!(hub.members("room"), [fast.id()]);
+    assert!(hub.members("other").is_empty());
+
+    // what was queued is still delivered, then the subscription ends
+    assert_eq!(slow.recv().await, text("1"));
+    assert_eq!(slow.recv().await, None);
+    slow.join("room");
+    assert_eq!(hub.members("room"), [fast.id()]);
+}
+
+#[tokio::test]
+async fn dropped_subscriptions_leave_every_room() {
+    let hub = Hub::new();
+    let kept = hub.register();
+    let dropped = hub.register();
+    let id = dropped.id();
+    kept.join("a");
+    dropped.join("a");
+    dropped.join("b");
+
+    drop(dropped);
+    assert_eq!(hub.members("a"), [kept.id()]);
+    assert!(hub.members("b").is_empty());
+    assert_eq!(hub.broadcast("b", "gone"), 0);
+    hub.join(id, "a");
+    assert_eq!(hub.members("a"), [kept.id()]);
+}
+
+#[tokio::test]
+async fn disconnected_sessions_are_closed_with_policy_violation() {
+    let hub = Hub::new()
+        .with_queue_size(1)
+        .with_slow_consumer(SlowConsumer::Disconnect);
+    let (mut peer, stream) = tokio::io::duplex(1 << 16);
+    let (sender, mut receiver) = split(stream, WebSocketState::default());
+    let receiving = tokio::spawn(async move { while receiver.next().await.is_some() {} });
+    let mut subscription = hub.register();
+    subscription.join("room");
+
+    assert_eq!(hub.broadcast("room", "queued"), 1);
+    assert_eq!(hub.broadcast("room", "overflow"), 0);
+    timeout(Duration::from_secs(5), subscription.forward(&sender))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let frame = Frame::read(&mut peer).await.unwrap();
+    assert_eq!(
+        (frame.header.opcode, frame.payload.as_slice()),
+        (Opcode::Text, b"queued".as_slice())
+    );
+    let frame = Frame::read(&mut peer).await.unwrap();
+    assert_eq!(frame.header.opcode, Opcode::Close);
+    assert_eq!(frame.payload.get(..2), Some(1008_u16.to_be_bytes().as_slice()));
+    receiving.abort();
+}
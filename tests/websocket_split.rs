@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use cliud::websocket::{Error, Frame, Message, Opcode, WebSocketState, split};
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn senders_do_not_need_the_receiver_to_be_polled() {
+    let (mut peer, stream) = tokio::io::duplex(1 << 20);
+    let (sender, mut receiver) = split(stream, WebSocketState::default());
+
+    let mut request = Frame::new(true, Opcode::Text, b"flood".to_vec());
+    request.header.mask = Some([1, 2, 3, 4]);
+    request.write(&mut peer).await.unwrap();
+
+    // answers from inside the receive loop, far more than the queue holds
+    let handler = tokio::spawn(async move {
+        while let Some(message) = receiver.next().await {
+            assert_eq!(message.unwrap(), Message::from("flood"));
+            for index in 0..1000 {
+                sender.send(index.to_string()).await.unwrap();
+            }
+        }
+    });
+    for index in 0..1000 {
+        let frame = timeout(Duration::from_secs(5), Frame::read(&mut peer))
+            .await
+            .expect("the queue was not drained")
+            .unwrap();
+        assert_eq!(frame.payload, index.to_string().as_bytes());
+    }
+    handler.abort();
+}
+
+#[tokio::test]
+async fn pings_must_fit_a_control_frame() {
+    let (mut peer, stream) = tokio::io::duplex(1 << 16);
+    let (sender, _receiver) = split(stream, WebSocketState::default());
+    assert!(matches!(sender.ping(vec![0; 126]).await, Err(Error::BadProtocol)));
+    sender.ping(vec![7; 125]).await.unwrap();
+
+    let frame = Frame::read(&mut peer).await.unwrap();
+    assert_eq!(frame.header.opcode, Opcode::Ping);
+    assert_eq!(frame.payload, [7; 125]);
+}
+
+#[tokio::test]
+async fn the_writer_stops_once_every_half_is_dropped() {
+    let (mut peer, stream) = tokio::io::duplex(1 << 16);
+    let (sender, receiver) = split(stream, WebSocketState::default());
+    sender.send("last").await.unwrap();
+    drop((sender, receiver));
+
+    let frame = Frame::read(&mut peer).await.unwrap();
+    assert_eq!(frame.payload, b"last");
+    assert!(Frame::read(&mut peer).await.is_err());
+}
@@ -0,0 +1,314 @@
+//! Timers and the ways a connection can end, against `WebSocketExt::run` and `split`, on a paused clock.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::time::Duration;
+
+use cliud::websocket::{
+    CloseCode, CloseFrame, Error, Frame, Message, Opcode, Result, WebSocket, WebSocketExt as _, WebSocketState, split,
+};
+use tokio::io::{AsyncWriteExt as _, DuplexStream, WriteHalf};
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// Closes the connection when told to, and records what it is told.
+struct Recorder {
+    stream: Mutex<DuplexStream>,
+    state: RwLock<WebSocketState>,
+    closes: Vec<CloseFrame>,
+}
+
+impl WebSocket for Recorder {
+    type Stream = DuplexStream;
+
+    async fn state(&self) -> impl Deref<Target = WebSocketState> {
+        self.state.read().await
+    }
+
+    async fn state_mut(&self) -> impl DerefMut<Target = WebSocketState> {
+        self.state.write().await
+    }
+
+    async fn stream_mut(&self) -> impl DerefMut<Target = Self::Stream> {
+        self.stream.lock().await
+    }
+
+    async fn on_message(&mut self, message: Message) -> Result<()> {
+        if message == Message::from("close") {
+            self.send_close(CloseFrame::normal()).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_close(&mut self, frame: CloseFrame) -> Result<()> {
+        self.closes.push(frame);
+        Ok(())
+    }
+}
+
+/// Runs a [`Recorder`] with `state`, returning the peer's end and the recorder once `run` returns.
+fn serve(state: WebSocketState) -> (DuplexStream, JoinHandle<(Result<()>, Recorder)>) {
+    let (peer, stream) = tokio::io::duplex(1 << 16);
+    let mut recorder = Recorder {
+        stream: Mutex::new(stream),
+        state: RwLock::new(state),
+        closes: Vec::new(),
+    };
+    let task = tokio::spawn(async move { (recorder.run().await, recorder) });
+    (peer, task)
+}
+
+async fn send(peer: &mut DuplexStream, opcode: Opcode, payload: &[u8]) {
+    let mut frame = Frame::new(true, opcode, payload.to_vec());
+    frame.header.mask = Some([1, 2, 3, 4]);
+    frame.write(peer).await.unwrap();
+}
+
+fn abnormal(frames: &[CloseFrame]) -> bool {
+    matches!(frames, [frame] if frame.code == CloseCode::Abnormal)
+}
+
+/// Never pings, so only the timeout under test fires.
+fn quiet() -> WebSocketState {
+    WebSocketState::default().with_ping_interval(Duration::from_secs(3600))
+}
+
+#[tokio::test(start_paused = true)]
+async fn run_gives_up_on_an_unanswered_close() {
+    let (mut peer, task) = serve(quiet().with_close_timeout(Duration::from_secs(5)));
+    send(&mut peer, Opcode::Text, b"close").await;
+
+    let close = Frame::read(&mut peer).await.unwrap();
+    assert_eq!(close.header.opcode, Opcode::Close);
+    assert_eq!(close.payload.get(..2), Some(1000_u16.to_be_bytes().as_slice()));
+    // the peer never answers, so the connection is shut down once the timeout passed
+    assert!(Frame::read(&mut peer).await.is_err());
+    let (result, recorder) = task.await.unwrap();
+    result.unwrap();
+    assert!(abnormal(&recorder.closes), "{:?}", recorder.closes);
+}
+
+#[tokio::test(start_paused = true)]
+async fn run_reports_peers_going_away_without_a_close() {
+    let (peer, task) = serve(quiet());
+    drop(peer);
+    let (result, recorder) = task.await.unwrap();
+    assert!(matches!(result, Err(Error::IO(_))), "{result:?}");
+    assert!(abnormal(&recorder.closes), "{:?}", recorder.closes);
+}
+
+#[tokio::test(start_paused = true)]
+async fn run_reports_timeouts() {
+    let state = WebSocketState::default()
+        .with_ping_interval(Duration::from_secs(1))
+        .with_pong_timeout(Duration::from_secs(1));
+    let (mut peer, task) = serve(state);
+    assert_eq!(Frame::read(&mut peer).await.unwrap().header.opcode, Opcode::Ping);
+    let (result, recorder) = task.await.unwrap();
+    assert!(matches!(result, Err(Error::PongTimeout)), "{result:?}");
+    assert!(abnormal(&recorder.closes), "{:?}", recorder.closes);
+
+    let (_peer, task) = serve(quiet().with_idle_timeout(Duration::from_secs(2)));
+    let (result, recorder) = task.await.unwrap();
+    assert!(matches!(result, Err(Error::IdleTimeout)), "{result:?}");
+    assert!(abnormal(&recorder.closes), "{:?}", recorder.closes);
+}
+
+#[tokio::test(start_paused = true)]
+async fn split_gives_up_on_an_unanswered_close() {
+    let (mut peer, stream) = tokio::io::duplex(1 << 16);
+    let (sender, mut receiver) = split(stream, quiet().with_close_timeout(Duration::from_secs(5)));
+    sender.close(CloseFrame::normal()).await.unwrap();
+
+    assert!(receiver.next().await.is_none());
+    assert_eq!(receiver.close_frame().unwrap().code, CloseCode::Abnormal);
+    assert_eq!(Frame::read(&mut peer).await.unwrap().header.opcode, Opcode::Close);
+    assert!(Frame::read(&mut peer).await.is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn split_reports_how_the_connection_failed() {
+    let (peer, stream) = tokio::io::duplex(1 << 16);
+    let (_sender, mut receiver) = split(stream, quiet());
+    drop(peer);
+    assert!(matches!(receiver.next().await, Some(Err(Error::IO(_)))));
+    assert_eq!(receiver.close_frame().unwrap().code, CloseCode::Abnormal);
+    assert!(receiver.next().await.is_none());
+
+    let (_peer, stream) = tokio::io::duplex(1 << 16);
+    let (_sender, mut receiver) = split(stream, quiet().with_idle_timeout(Duration::from_secs(2)));
+    assert!(matches!(receiver.next().await, Some(Err(Error::IdleTimeout))));
+    assert_eq!(receiver.close_frame().unwrap().code, CloseCode::Abnormal);
+
+    // a peer at fault is told why
+    let (mut peer, stream) = tokio::io::duplex(1 << 16);
+    let (_sender, mut receiver) = split(stream, quiet());
+    send(&mut peer, Opcode::Text, &[0xff]).await;
+    assert!(matches!(receiver.next().await, Some(Err(Error::InvalidUtf8))));
+    assert_eq!(receiver.close_frame().unwrap().code, CloseCode::InvalidPayload);
+    let close = Frame::read(&mut peer).await.unwrap();
+    assert_eq!(close.payload.get(..2), Some(1007_u16.to_be_bytes().as_slice()));
+}
+
+/// How a server ended: what `run` or the receiver returned, how it reported the close, and the latencies it kept.
+struct Outcome {
+    result: Result<()>,
+    close: Option<CloseFrame>,
+    latencies: Vec<Duration>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Server {
+    Run,
+    Split,
+}
+
+impl Server {
+    fn spawn(self, state: WebSocketState) -> (DuplexStream, JoinHandle<Outcome>) {
+        match self {
+            Self::Run => {
+                let (peer, task) = serve(state);
+                let outcome = tokio::spawn(async move {
+                    let (result, recorder) = task.await.unwrap();
+                    assert!(recorder.closes.len() <= 1, "{:?}", recorder.closes);
+                    Outcome {
+                        result,
+                        close: recorder.closes.into_iter().next(),
+                        latencies: recorder.state.read().await.latencies().collect(),
+                    }
+                });
+                (peer, outcome)
+            }
+            Self::Split => {
+                let (peer, stream) = tokio::io::duplex(1 << 16);
+                let outcome = tokio::spawn(async move {
+                    let (_sender, mut receiver) = split(stream, state);
+                    let mut result = Ok(());
+                    while let Some(received) = receiver.next().await {
+                        if let Err(error) = received {
+                            result = Err(error);
+                        }
+                    }
+                    Outcome {
+                        result,
+                        close: receiver.close_frame().cloned(),
+                        latencies: receiver.state().latencies().collect(),
+                    }
+                });
+                (peer, outcome)
+            }
+        }
+    }
+}
+
+/// How the client answers the `n`th ping carrying `payload`: after how long, and with what, if at all.
+type Answer = fn(u64, Vec<u8>) -> Option<(Duration, Vec<u8>)>;
+
+/// Plays the client for `seconds`, sending a message every second and answering pings as `answer` says, then closes
+/// the connection normally; returns how many pings it saw.
+async fn client(peer: DuplexStream, seconds: u64, answer: Answer) -> u64 {
+    async fn write(writer: &Mutex<WriteHalf<DuplexStream>>, opcode: Opcode, payload: Vec<u8>) -> Result<()> {
+        let mut frame = Frame::new(true, opcode, payload);
+        frame.header.mask = Some([1, 2, 3, 4]);
+        frame.write(&mut *writer.lock().await).await
+    }
+
+    let (mut reader, writer) = tokio::io::split(peer);
+    let writer = Arc::new(Mutex::new(writer));
+    let answering = Arc::clone(&writer);
+    let answerer = tokio::spawn(async move {
+        let mut pings = 0;
+        while let Ok(frame) = Frame::read(&mut reader).await {
+            if frame.header.opcode != Opcode::Ping {
+                continue;
+            }
+            pings += 1;
+            if let Some((delay, payload)) = answer(pings, frame.payload) {
+                sleep(delay).await;
+                drop(write(&answering, Opcode::Pong, payload).await);
+            }
+        }
+        pings
+    });
+    for _ in 0..seconds {
+        // the server may have given up already
+        if write(&writer, Opcode::Text, b"tick".to_vec()).await.is_err() {
+            break;
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+    drop(write(&writer, Opcode::Close, CloseFrame::normal().to_payload()).await);
+    drop(writer.lock().await.shutdown().await);
+    answerer.await.unwrap()
+}
+
+fn echo(_ping: u64, payload: Vec<u8>) -> Option<(Duration, Vec<u8>)> {
+    Some((Duration::ZERO, payload))
+}
+
+#[tokio::test(start_paused = true)]
+async fn pings_keep_coming_on_a_busy_connection() {
+    for server in [Server::Run, Server::Split] {
+        let state = WebSocketState::default()
+            .with_ping_interval(Duration::from_secs(5))
+            .with_pong_timeout(Duration::from_secs(2))
+            .with_idle_timeout(Duration::from_secs(3));
+        let (peer, outcome) = server.spawn(state);
+        // a message every second keeps the idle timeout away, but not the pings at 5, 10, 15, 20 and 25 seconds
+        let pings = client(peer, 28, echo).await;
+        let outcome = outcome.await.unwrap();
+        assert!(outcome.result.is_ok(), "{server:?} {:?}", outcome.result);
+        assert_eq!(outcome.close.unwrap().code, CloseCode::Normal, "{server:?}");
+        assert_eq!(pings, 5, "{server:?}");
+        assert_eq!(outcome.latencies, [Duration::ZERO; 5], "{server:?}");
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn pongs_must_answer_the_pending_ping() {
+    fn mismatched(_ping: u64, mut payload: Vec<u8>) -> Option<(Duration, Vec<u8>)> {
+        payload.reverse();
+        Some((Duration::ZERO, payload))
+    }
+
+    for server in [Server::Run, Server::Split] {
+        let state = WebSocketState::default()
+            .with_ping_interval(Duration::from_secs(1))
+            .with_pong_timeout(Duration::from_secs(2));
+        let (peer, outcome) = server.spawn(state);
+        let pings = client(peer, 10, mismatched).await;
+        let outcome = outcome.await.unwrap();
+        assert!(
+            matches!(outcome.result, Err(Error::PongTimeout)),
+            "{server:?} {:?}",
+            outcome.result
+        );
+        assert_eq!(outcome.close.unwrap().code, CloseCode::Abnormal, "{server:?}");
+        assert_eq!(pings, 1, "{server:?}");
+        assert!(outcome.latencies.is_empty(), "{server:?}");
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn only_the_last_latencies_are_kept() {
+    // the nth ping is answered after n milliseconds
+    fn slower(ping: u64, payload: Vec<u8>) -> Option<(Duration, Vec<u8>)> {
+        Some((Duration::from_millis(ping), payload))
+    }
+
+    for server in [Server::Run, Server::Split] {
+        let state = WebSocketState::default()
+            .with_ping_interval(Duration::from_millis(1500))
+            .with_pong_timeout(Duration::from_secs(1));
+        let (peer, outcome) = server.spawn(state);
+        // pings every one and a half seconds, the last at 28.5
+        let pings = client(peer, 29, slower).await;
+        let outcome = outcome.await.unwrap();
+        assert!(outcome.result.is_ok(), "{server:?} {:?}", outcome.result);
+        assert_eq!(pings, 19, "{server:?}");
+        assert_eq!(WebSocketState::LATENCY_HISTORY, 16);
+        let kept = (4..=19).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(outcome.latencies, kept, "{server:?}");
+    }
+}
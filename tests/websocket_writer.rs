@@ -0,0 +1,153 @@
+use std::ops::{Deref, DerefMut};
+
+use cliud::websocket::{Error, Frame, Message, Opcode, WebSocket, WebSocketExt as _, WebSocketState, split};
+use tokio::io::DuplexStream;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+struct Socket {
+    stream: Mutex<DuplexStream>,
+    state: RwLock<WebSocketState>,
+}
+
+impl WebSocket for Socket {
+    type Stream = DuplexStream;
+
+    async fn state(&self) -> impl Deref<Target = WebSocketState> {
+        self.state.read().await
+    }
+
+    async fn state_mut(&self) -> impl DerefMut<Target = WebSocketState> {
+        self.state.write().await
+    }
+
+    async fn stream_mut(&self) -> impl DerefMut<Target = Self::Stream> {
+        self.stream.lock().await
+    }
+}
+
+/// A socket, and a task collecting the frames it sends up to the final one.
+fn socket(state: WebSocketState) -> (Socket, JoinHandle<Vec<Frame>>) {
+    let (mut peer, stream) = tokio::io::duplex(1 << 16);
+    let frames = tokio::spawn(async move {
+        let mut frames = Vec::new();
+        loop {
+            let frame = Frame::read(&mut peer).await.unwrap();
+            let fin = frame.header.fin;
+            frames.push(frame);
+            if fin {
+                return frames;
+            }
+        }
+    });
+    let socket = Socket {
+        stream: Mutex::new(stream),
+        state: RwLock::new(state),
+    };
+    (socket, frames)
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|index| index.to_le_bytes()[0] ^ index.to_le_bytes()[1])
+        .collect()
+}
+
+#[tokio::test]
+async fn writes_are_cut_into_full_fragments() {
+    for writes in [
+        vec![0],
+        vec![1],
+        vec![4],
+        vec![5],
+        vec![3, 3, 3],
+        vec![9, 1, 0, 2],
+        vec![20],
+        vec![2, 17, 4],
+    ] {
+        let (socket, frames) = socket(WebSocketState::default().with_fragment_size(4));
+        let data = pattern(writes.iter().sum());
+        let mut writer = socket.message_writer(Opcode::Binary).await.unwrap();
+        let mut rest = data.as_slice();
+        for len in &writes {
+            let (write, tail) = rest.split_at(*len);
+            writer.write(write).await.unwrap();
+            rest = tail;
+        }
+        writer.finish().await.unwrap();
+
+        let frames = frames.await.unwrap();
+        let (last, full) = frames.split_last().unwrap();
+        assert!(full.iter().all(|frame| frame.payload.len() == 4), "{writes:?}");
+        assert!(!last.payload.is_empty() || data.is_empty(), "{writes:?}");
+        assert!(last.payload.len() <= 4, "{writes:?}");
+        assert_eq!(frames.first().unwrap().header.opcode, Opcode::Binary);
+        assert!(
+            frames
+                .iter()
+                .skip(1)
+                .all(|frame| frame.header.opcode == Opcode::Continuation)
+        );
+        assert_eq!(
+            frames.into_iter().flat_map(|frame| frame.payload).collect::<Vec<_>>(),
+            data
+        );
+    }
+}
+
+#[tokio::test]
+async fn large_messages_are_sent_in_linear_time() {
+    let (mut socket, frames) = socket(WebSocketState::default().with_fragment_size(1024));
+    let data = pattern(16 << 20);
+    socket.send_binary(data.clone()).await.unwrap();
+
+    let frames = frames.await.unwrap();
+    assert_eq!(frames.len(), 16 << 10);
+    assert_eq!(
+        frames.into_iter().flat_map(|frame| frame.payload).collect::<Vec<_>>(),
+        data
+    );
+}
+
+#[tokio::test]
+async fn a_zero_fragment_size_is_clamped() {
+    let mut state = WebSocketState::default();
+    state.fragment_size = 0;
+    let (mut socket, frames) = socket(state);
+    socket.send_text("abc".to_owned()).await.unwrap();
+
+    let frames = frames.await.unwrap();
+    assert_eq!(
+        frames.iter().map(|frame| frame.payload.as_slice()).collect::<Vec<_>>(),
+        [b"a", b"b", b"c"]
+    );
+}
+
+#[tokio::test]
+async fn only_data_messages_can_be_fragmented() {
+    let (socket, _frames) = socket(WebSocketState::default());
+    for opcode in [Opcode::Continuation, Opcode::Close, Opcode::Ping, Opcode::Pong] {
+        assert!(matches!(socket.message_writer(opcode).await, Err(Error::BadProtocol)));
+    }
+}
+
+#[tokio::test]
+async fn split_senders_clamp_the_fragment_size() {
+    let mut state = WebSocketState::default();
+    state.fragment_size = 0;
+    let (mut peer, stream) = tokio::io::duplex(1 << 16);
+    let (sender, mut receiver) = split(stream, state);
+    let receiving = tokio::spawn(async move { while receiver.next().await.is_some() {} });
+    sender.send(Message::Text("abc".to_owned())).await.unwrap();
+
+    let mut payloads = Vec::new();
+    loop {
+        let frame = Frame::read(&mut peer).await.unwrap();
+        payloads.push(frame.payload);
+        if frame.header.fin {
+            break;
+        }
+    }
+    assert_eq!(payloads, [b"a", b"b", b"c"]);
+    receiving.abort();
+}